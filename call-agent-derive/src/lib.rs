@@ -0,0 +1,128 @@
+//! Derive macro for the `Tool` trait of `call-agent`.
+//!
+//! Use it through the `derive` feature of `call-agent`, which re-exports it as
+//! `call_agent::chat::function::Tool`.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, DeriveInput, Expr, ExprLit, Lit, LitStr, Meta, Type};
+
+/// Derive `Tool` for a struct with a typed `call` method.
+///
+/// The parameter schema is generated from the argument type, which must implement
+/// `serde::Deserialize` and `schemars::JsonSchema` (re-exported as `call_agent::schemars`). The struct must have an inherent
+/// `fn call(&self, args: Args) -> Result<String, String>`, which the generated `Tool::run` calls.
+/// It is not named `run`, so a missing method is a compile error instead of a call of `Tool::run` itself.
+///
+/// Attributes of `#[tool(...)]`:
+/// - `args = Type`: the argument type (required)
+/// - `name = "..."`: the tool name; defaults to the struct name in snake_case
+/// - `description = "..."`: the tool description; defaults to the doc comment of the struct
+#[proc_macro_derive(Tool, attributes(tool))]
+pub fn derive_tool(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let mut name: Option<LitStr> = None;
+    let mut description: Option<LitStr> = None;
+    let mut args: Option<Type> = None;
+
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("tool")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("description") {
+                description = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("args") {
+                args = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("expected `name`, `description` or `args`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    let ident = &input.ident;
+    let args = args.ok_or_else(|| syn::Error::new(input.span(), "missing `#[tool(args = Type)]`"))?;
+    let name = name
+        .map(|name| name.value())
+        .unwrap_or_else(|| to_snake_case(&ident.to_string()));
+    let description = match description {
+        Some(description) => description.value(),
+        None => doc_comment(input).ok_or_else(|| {
+            syn::Error::new(input.span(), "missing `#[tool(description = \"...\")]` or doc comment")
+        })?,
+    };
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::call_agent::chat::function::Tool for #ident #ty_generics #where_clause {
+            fn def_name(&self) -> &str {
+                #name
+            }
+
+            fn def_description(&self) -> &str {
+                #description
+            }
+
+            fn def_parameters(&self) -> ::call_agent::__private::serde_json::Value {
+                ::call_agent::chat::function::tool_parameters::<#args>()
+            }
+
+            fn run(
+                &self,
+                args: ::call_agent::__private::serde_json::Value,
+            ) -> ::core::result::Result<::std::string::String, ::std::string::String> {
+                let args: #args = ::call_agent::__private::serde_json::from_value(args)
+                    .map_err(|e| ::std::format!("invalid arguments: {}", e))?;
+                Self::call(self, args)
+            }
+        }
+    })
+}
+
+/// Join the doc comment lines of the item.
+fn doc_comment(input: &DeriveInput) -> Option<String> {
+    let lines: Vec<String> = input
+        .attrs
+        .iter()
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(meta) if meta.path.is_ident("doc") => match &meta.value {
+                Expr::Lit(ExprLit { lit: Lit::Str(doc), .. }) => Some(doc.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    let doc = lines.join("\n").trim().to_string();
+    if doc.is_empty() {
+        None
+    } else {
+        Some(doc)
+    }
+}
+
+/// Convert a type name to snake_case, keeping runs of capitals as one word (`HTTPTool` -> `http_tool`).
+fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let prev_lower = i > 0 && !chars[i - 1].is_uppercase();
+            let next_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            let prev_upper = i > 0 && chars[i - 1].is_uppercase();
+            if prev_lower || (prev_upper && next_lower) {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(*c);
+        }
+    }
+    snake
+}
@@ -0,0 +1,533 @@
+use std::{collections::{HashMap, VecDeque}, time::Duration};
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+    api::{APIErrorResponse, APIResponse, APIResponseHeaders, APIUsage, ToolChoice},
+    backend::ChatBackend,
+    client::{build_http_client, custom_headers, send_with_retry, APIResult, ModelConfig, RetryConfig, DEFAULT_TIMEOUT},
+    err::ClientError,
+    function::{FunctionCall, FunctionCallInner, ToolDef},
+    prompt::{Choice, FinishReason, Message, MessageContext, ResponseMessage},
+};
+
+/// Default endpoint of the Anthropic API.
+pub const ANTHROPIC_END_POINT: &str = "https://api.anthropic.com/v1";
+/// Default value of the `anthropic-version` header.
+pub const ANTHROPIC_VERSION: &str = "2023-06-01";
+/// `max_tokens` sent when `ModelConfig::max_completion_tokens` is not set, since Anthropic requires it.
+pub const DEFAULT_MAX_TOKENS: u64 = 4096;
+
+/// Backend for the Anthropic Messages API.
+///
+/// Translates the prompt into Anthropic's `messages` format and maps the response back
+/// into the OpenAI shape, so the same `Tool` implementations can be used with Claude models.
+///
+/// - System and developer messages are joined into the top-level `system` field.
+/// - Tool results are sent as `tool_result` blocks in a user turn.
+/// - Audio input is not supported.
+///
+/// Timeouts, retries, custom headers and TLS settings work the same as on `OpenAIClient`.
+///
+/// # Example
+///
+/// ```
+/// # use call_agent::chat::{anthropic::AnthropicClient, client::{ModelConfig, OpenAIClient}};
+/// let mut client = OpenAIClient::new("https://api.openai.com/v1/", None);
+/// client.set_model_config(&ModelConfig::new("claude-sonnet-4-5"));
+///
+/// // Tools registered on `client` are sent to Anthropic.
+/// let state = client.create_prompt_with_backend(AnthropicClient::new("YOUR_API_KEY"));
+/// ```
+#[derive(Clone)]
+pub struct AnthropicClient {
+    /// HTTP client
+    pub client: Client,
+    /// API endpoint
+    pub end_point: String,
+    /// API key sent as the `x-api-key` header
+    pub api_key: String,
+    /// Value of the `anthropic-version` header
+    /// default: "2023-06-01"
+    pub version: String,
+    /// Retry policy for rate limited or failed requests.
+    /// None disables retrying.
+    pub retry_config: Option<RetryConfig>,
+    /// Custom headers added to every request.
+    /// They are applied last, so they override the built-in headers with the same name.
+    pub headers: HashMap<String, String>,
+    /// Total timeout of a request, including reading the response body.
+    /// default: 120 seconds
+    /// Set with `with_timeout`.
+    timeout: Option<Duration>,
+    /// Timeout for establishing a connection.
+    /// Set with `with_connect_timeout`.
+    connect_timeout: Option<Duration>,
+    /// Accept invalid TLS certificates (see `danger_accept_invalid_certs`).
+    accept_invalid_certs: bool,
+}
+
+impl AnthropicClient {
+    /// Create a new AnthropicClient for the default endpoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - The API key.
+    pub fn new(api_key: &str) -> Self {
+        Self::with_end_point(ANTHROPIC_END_POINT, api_key)
+    }
+
+    /// Create a new AnthropicClient for another endpoint (e.g. a proxy).
+    ///
+    /// # Arguments
+    ///
+    /// * `end_point` - The endpoint of the Anthropic API.
+    /// * `api_key` - The API key.
+    pub fn with_end_point(end_point: &str, api_key: &str) -> Self {
+        // If the client cannot be built, keep the default one without a timeout.
+        let (client, timeout) = match build_http_client(Some(DEFAULT_TIMEOUT), None, false) {
+            Ok(client) => (client, Some(DEFAULT_TIMEOUT)),
+            Err(_) => (Client::new(), None),
+        };
+        Self {
+            client,
+            end_point: end_point.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            version: ANTHROPIC_VERSION.to_string(),
+            retry_config: None,
+            headers: HashMap::new(),
+            timeout,
+            connect_timeout: None,
+            accept_invalid_certs: false,
+        }
+    }
+
+    /// Set a custom header sent with every request.
+    ///
+    /// If a header with the same name is already set, it will be overwritten.
+    /// Headers with an invalid name or value are skipped when sending.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The header name.
+    /// * `value` - The header value.
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        self.headers.insert(key.to_string(), value.to_string());
+    }
+
+    /// Set the retry policy for rate limited or failed requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry_config` - The retry policy.
+    pub fn set_retry_config(&mut self, retry_config: &RetryConfig) {
+        self.retry_config = Some(retry_config.clone());
+    }
+
+    /// Set the total timeout of a request.
+    ///
+    /// This rebuilds the underlying HTTP client.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The total timeout, including reading the response body.
+    ///
+    /// # Returns
+    ///
+    /// The client, or a ClientError if the HTTP client cannot be built.
+    pub fn with_timeout(mut self, timeout: Duration) -> Result<Self, ClientError> {
+        self.timeout = Some(timeout);
+        self.client = self.build_http_client()?;
+        Ok(self)
+    }
+
+    /// Remove the total timeout of a request.
+    ///
+    /// This rebuilds the underlying HTTP client.
+    ///
+    /// # Returns
+    ///
+    /// The client, or a ClientError if the HTTP client cannot be built.
+    pub fn without_timeout(mut self) -> Result<Self, ClientError> {
+        self.timeout = None;
+        self.client = self.build_http_client()?;
+        Ok(self)
+    }
+
+    /// Set the timeout for establishing a connection.
+    ///
+    /// This rebuilds the underlying HTTP client.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The connect timeout.
+    ///
+    /// # Returns
+    ///
+    /// The client, or a ClientError if the HTTP client cannot be built.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Result<Self, ClientError> {
+        self.connect_timeout = Some(timeout);
+        self.client = self.build_http_client()?;
+        Ok(self)
+    }
+
+    /// Accept invalid TLS certificates, e.g. the self-signed certificate of a local proxy.
+    ///
+    /// # Warning
+    ///
+    /// This disables certificate validation entirely, see `OpenAIClient::danger_accept_invalid_certs`.
+    /// Only use it against endpoints you control, never in production. Disabled by default.
+    ///
+    /// This rebuilds the underlying HTTP client.
+    ///
+    /// # Arguments
+    ///
+    /// * `accept` - True to accept invalid certificates.
+    ///
+    /// # Returns
+    ///
+    /// The client, or a ClientError if the HTTP client cannot be built.
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Result<Self, ClientError> {
+        self.accept_invalid_certs = accept;
+        self.client = self.build_http_client()?;
+        Ok(self)
+    }
+
+    /// The total timeout of a request set with `with_timeout`.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// The connect timeout set with `with_connect_timeout`.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    /// Build the HTTP client from the current settings.
+    fn build_http_client(&self) -> Result<Client, ClientError> {
+        build_http_client(self.timeout, self.connect_timeout, self.accept_invalid_certs)
+    }
+
+    /// Build the Anthropic request body.
+    fn build_request<'a>(
+        prompt: &VecDeque<Message>,
+        tools: &'a [ToolDef],
+        config: &'a ModelConfig,
+        tool_choice: Option<&ToolChoice>,
+    ) -> Result<AnthropicRequest<'a>, ClientError> {
+        config.validate()?;
+
+        let mut system: Vec<&str> = Vec::new();
+        let mut messages: Vec<AnthropicMessage> = Vec::new();
+        for message in prompt {
+            let (role, content) = match message {
+                Message::System { content, .. } | Message::Developer { content, .. } => {
+                    system.push(content);
+                    continue;
+                }
+                Message::User { content, .. } => ("user", to_blocks(content)?),
+                Message::Assistant { content, tool_calls, .. } => {
+                    let mut blocks = to_blocks(content)?;
+                    for call in tool_calls.iter().flatten() {
+                        blocks.push(ContentBlock::ToolUse {
+                            id: call.id.clone(),
+                            name: call.function.name.clone(),
+                            input: tool_input(&call.function.arguments),
+                        });
+                    }
+                    ("assistant", blocks)
+                }
+                Message::Tool { tool_call_id, content, .. } => (
+                    "user",
+                    vec![ContentBlock::ToolResult {
+                        tool_use_id: tool_call_id.clone(),
+                        content: to_blocks(content)?,
+                    }],
+                ),
+            };
+            // Anthropic rejects empty turns.
+            if content.is_empty() {
+                continue;
+            }
+            // Consecutive turns of the same role (e.g. several tool results) are merged.
+            match messages.last_mut() {
+                Some(last) if last.role == role => last.content.extend(content),
+                _ => messages.push(AnthropicMessage { role, content }),
+            }
+        }
+
+        let disable_parallel_tool_use = config.parallel_tool_calls.map(|parallel| !parallel).filter(|disable| *disable);
+        let tool_choice = if tools.is_empty() {
+            None
+        } else {
+            tool_choice.map(|choice| match choice {
+                ToolChoice::None => AnthropicToolChoice::None,
+                ToolChoice::Auto => AnthropicToolChoice::Auto { disable_parallel_tool_use },
+                ToolChoice::Required => AnthropicToolChoice::Any { disable_parallel_tool_use },
+                ToolChoice::Function(name) => AnthropicToolChoice::Tool { name: name.clone(), disable_parallel_tool_use },
+                ToolChoice::Raw(value) => AnthropicToolChoice::Raw(value.clone()),
+            })
+        };
+
+        Ok(AnthropicRequest {
+            model: &config.model,
+            max_tokens: config.max_completion_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            system: if system.is_empty() { None } else { Some(system.join("\n\n")) },
+            messages,
+            tools: tools
+                .iter()
+                .map(|tool| AnthropicTool {
+                    name: &tool.function.name,
+                    description: &tool.function.description,
+                    input_schema: &tool.function.parameters,
+                })
+                .collect(),
+            tool_choice,
+            temperature: config.temperature,
+            top_p: config.top_p,
+            stop_sequences: config.stop.as_ref(),
+        })
+    }
+}
+
+#[async_trait]
+impl ChatBackend for AnthropicClient {
+    async fn complete(
+        &self,
+        prompt: &VecDeque<Message>,
+        tools: &[ToolDef],
+        config: &ModelConfig,
+        tool_choice: Option<&ToolChoice>,
+    ) -> Result<APIResult, ClientError> {
+        let url = format!("{}/messages", self.end_point);
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(ClientError::InvalidEndpoint);
+        }
+
+        let request = Self::build_request(prompt, tools, config, tool_choice)?;
+        let res = send_with_retry(self.retry_config.as_ref(), || {
+            self.client
+                .post(&url)
+                .header("x-api-key", &self.api_key)
+                .header("anthropic-version", &self.version)
+                .headers(custom_headers(&self.headers))
+                .json(&request)
+        })
+        .await?;
+
+        let status = res.status();
+        let headers = APIResponseHeaders::from_headers(res.headers());
+        let text = res.text().await.map_err(ClientError::from_reqwest)?;
+        log::debug!("Response: {}", text);
+
+        // Anthropic errors have the same `error.type` / `error.message` shape as OpenAI.
+        if let Ok(APIErrorResponse { error: Some(err) }) = serde_json::from_str(&text) {
+            return Err(err.into());
+        }
+        if !status.is_success() {
+            return Err(ClientError::InvalidResponse);
+        }
+        let response: AnthropicResponse = serde_json::from_str(&text).map_err(|_| ClientError::InvalidResponse)?;
+
+        Ok(APIResult {
+            response: response.into_api_response(),
+            headers,
+        })
+    }
+}
+
+/// Convert message contexts into Anthropic content blocks.
+fn to_blocks(content: &[MessageContext]) -> Result<Vec<ContentBlock>, ClientError> {
+    content
+        .iter()
+        .map(|context| match context {
+            MessageContext::Text(text) => Ok(ContentBlock::Text { text: text.clone() }),
+            MessageContext::Image(image) => {
+                let source = match image
+                    .url
+                    .strip_prefix("data:")
+                    .and_then(|data| data.split_once(";base64,"))
+                {
+                    Some((media_type, data)) => ImageSource::Base64 {
+                        media_type: media_type.to_string(),
+                        data: data.to_string(),
+                    },
+                    None => ImageSource::Url { url: image.url.clone() },
+                };
+                Ok(ContentBlock::Image { source })
+            }
+            MessageContext::Audio(_) => Err(ClientError::InvalidInput(
+                "audio input is not supported by the Anthropic API".to_string(),
+            )),
+        })
+        .collect()
+}
+
+/// Anthropic requires the tool input to be an object.
+fn tool_input(arguments: &Value) -> Value {
+    match arguments {
+        Value::Object(_) => arguments.clone(),
+        Value::String(raw) => serde_json::from_str::<Value>(raw)
+            .ok()
+            .filter(Value::is_object)
+            .unwrap_or_else(|| Value::Object(Default::default())),
+        _ => Value::Object(Default::default()),
+    }
+}
+
+#[derive(Serialize)]
+struct AnthropicRequest<'a> {
+    model: &'a str,
+    max_tokens: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthropicMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<AnthropicTool<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<AnthropicToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<&'a Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct AnthropicMessage {
+    role: &'static str,
+    content: Vec<ContentBlock>,
+}
+
+#[derive(Serialize)]
+struct AnthropicTool<'a> {
+    name: &'a str,
+    description: &'a str,
+    input_schema: &'a Value,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicToolChoice {
+    None,
+    Auto {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        disable_parallel_tool_use: Option<bool>,
+    },
+    Any {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        disable_parallel_tool_use: Option<bool>,
+    },
+    Tool {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        disable_parallel_tool_use: Option<bool>,
+    },
+    /// Sent as is.
+    #[serde(untagged)]
+    Raw(Value),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text {
+        text: String,
+    },
+    Image {
+        source: ImageSource,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: Vec<ContentBlock>,
+    },
+    /// Blocks this adapter does not map (e.g. thinking).
+    #[serde(other, skip_serializing)]
+    Other,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+#[derive(Deserialize)]
+struct AnthropicResponse {
+    #[serde(default)]
+    id: String,
+    model: Option<String>,
+    #[serde(default)]
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize)]
+struct AnthropicUsage {
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+}
+
+impl AnthropicResponse {
+    /// Map the response into the OpenAI chat completion shape.
+    fn into_api_response(self) -> APIResponse {
+        let mut text = String::new();
+        let mut tool_calls = Vec::new();
+        for block in self.content {
+            match block {
+                ContentBlock::Text { text: part } => text.push_str(&part),
+                ContentBlock::ToolUse { id, name, input } => tool_calls.push(FunctionCall {
+                    id,
+                    tool_type: "function".to_string(),
+                    function: FunctionCallInner { name, arguments: input },
+                }),
+                _ => {}
+            }
+        }
+
+        let finish_reason = match self.stop_reason.as_deref() {
+            Some("end_turn") | Some("stop_sequence") | None => FinishReason::Stop,
+            Some("max_tokens") => FinishReason::Length,
+            Some("tool_use") => FinishReason::ToolCalls,
+            Some("refusal") => FinishReason::ContentFilter,
+            Some(other) => FinishReason::Other(other.to_string()),
+        };
+
+        APIResponse {
+            id: self.id,
+            object: "chat.completion".to_string(),
+            model: self.model,
+            choices: Some(vec![Choice {
+                index: 0,
+                message: ResponseMessage {
+                    role: "assistant".to_string(),
+                    content: if text.is_empty() { None } else { Some(text) },
+                    tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+                    refusal: None,
+                    annotations: None,
+                },
+                finish_reason,
+                logprobs: None,
+            }]),
+            error: None,
+            usage: self.usage.map(|usage| APIUsage {
+                prompt_tokens: usage.input_tokens,
+                completion_tokens: usage.output_tokens,
+                total_tokens: Some(usage.input_tokens.unwrap_or(0) + usage.output_tokens.unwrap_or(0)),
+                ..Default::default()
+            }),
+            created: None,
+        }
+    }
+}
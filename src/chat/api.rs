@@ -1,10 +1,12 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
-use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+use reqwest::header::HeaderMap;
+use serde::{ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
 
+use super::err::ClientError;
 use super::function::ToolDef;
 
-use super::prompt::{Choice, Message};
+use super::prompt::{Choice, Message, StreamChoice};
 
 /// API Response Headers struct
 #[derive(Debug, Clone)]
@@ -22,6 +24,32 @@ pub struct APIResponseHeaders {
     pub extra_other: Vec<(String, String)>,
 }
 
+impl APIResponseHeaders {
+    /// Extract the rate limit information from the response headers.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let parse = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok().and_then(|v| v.parse().ok()))
+        };
+        Self {
+            retry_after: parse("Retry-After"),
+            reset: parse("X-RateLimit-Reset"),
+            rate_limit: parse("X-RateLimit-Remaining"),
+            limit: parse("X-RateLimit-Limit"),
+            extra_other: headers
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        k.as_str().to_string(),
+                        v.to_str().unwrap_or("").to_string(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
 /// API Request structure for sending prompt and function information
 #[derive(Debug, Deserialize)]
 pub struct APIRequest {
@@ -35,13 +63,9 @@ pub struct APIRequest {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub tools: Vec<ToolDef>,
 
-    /// Instructions for function calls:
-    /// - "auto": AI will make one or more function calls if needed
-    /// - "none": No function calls will be made
-    /// - "required": One or more function calls are mandatory
-    /// - {"type": "function", "function": {"name": "<function_name>"}}: Calls the function <function_name>
-    #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub tool_choice: serde_json::Value,
+    /// Instructions for function calls (see `ToolChoice`)
+    /// Not serialized when it is `None` or no tools are defined
+    pub tool_choice: Option<ToolChoice>,
 
     /// Specifies whether to make parallel tool calls
     /// default: true
@@ -55,9 +79,13 @@ pub struct APIRequest {
     pub temperature: Option<f64>,
 
     /// Specifies the maximum number of tokens generated by the model
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "max_tokens", skip_serializing_if = "Option::is_none")]
     pub max_completion_tokens: Option<u64>,
 
+    /// Name of the field `max_completion_tokens` is sent as (see `TokenParam`)
+    #[serde(skip)]
+    pub token_param: TokenParam,
+
     /// Specifies the width of the probability distribution for selecting the next token
     /// Lower values result in more predictable text
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -67,18 +95,135 @@ pub struct APIRequest {
     /// - "low": Low effort
     /// - "medium": Medium effort
     /// - "high": High effort
+    ///
     /// default: "medium"
     #[serde(skip_serializing_if = "Option::is_none")]
     pub reasoning_effort: Option<String>,
 
     /// Specifies whether to apply a repetition penalty to the model
-    /// Range: 2.0..-2.0
+    /// Range: -2.0..2.0
     #[serde(skip_serializing_if = "Option::is_none")]
     pub presence_penalty: Option<f64>,
 
+    /// Specifies whether to apply a frequency penalty to the model
+    /// Range: -2.0..2.0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f64>,
+
+    /// Sequences where the model stops generating further tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+
+    /// Bias added to the logits of the given token ids
+    /// Token ids are specific to the tokenizer of the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<HashMap<u32, f64>>,
+
+    /// Number of completions to generate
+    /// default: 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+
+    /// A stable identifier of the end user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+
+    /// Whether to return log probabilities of the output tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+
+    /// Number of most likely alternatives to return at each token position
+    /// Range: 0..20
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+
     /// Options for performing web search with available models
     #[serde(skip_serializing_if = "Option::is_none")]
     pub web_search_options: Option<WebSearchOptions>,
+
+    /// Specifies the format that the model must output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+
+    /// Specifies whether to stream the response as server-sent events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+
+    /// Options for the streaming response
+    /// Only serialized when `stream` is true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+}
+
+/// Options for the streaming response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamOptions {
+    /// Send an extra chunk with the token usage of the whole request before `data: [DONE]`
+    pub include_usage: bool,
+}
+
+/// Request field used to send the output token limit
+///
+/// Older models and some OpenAI compatible servers only accept the deprecated `max_tokens`
+/// and reject `max_completion_tokens`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TokenParam {
+    /// Send `max_completion_tokens` (default)
+    #[default]
+    MaxCompletionTokens,
+    /// Send `max_tokens`
+    MaxTokens,
+}
+
+/// Instructions for function calls
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolChoice {
+    /// "none": No function calls will be made
+    None,
+    /// "auto": AI will make one or more function calls if needed
+    Auto,
+    /// "required": One or more function calls are mandatory
+    Required,
+    /// {"type": "function", "function": {"name": "<function_name>"}}: Calls the function <function_name>
+    Function(String),
+    /// Any other value, sent as is (e.g. options added to the API later)
+    Raw(serde_json::Value),
+}
+
+impl Serialize for ToolChoice {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            ToolChoice::None => serializer.serialize_str("none"),
+            ToolChoice::Auto => serializer.serialize_str("auto"),
+            ToolChoice::Required => serializer.serialize_str("required"),
+            ToolChoice::Function(name) => {
+                serde_json::json!({"type": "function", "function": {"name": name}}).serialize(serializer)
+            }
+            ToolChoice::Raw(value) => value.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ToolChoice {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let choice = match value.as_str() {
+            Some("none") => ToolChoice::None,
+            Some("auto") => ToolChoice::Auto,
+            Some("required") => ToolChoice::Required,
+            _ => match value["function"]["name"].as_str() {
+                Some(name) if value["type"] == "function" => ToolChoice::Function(name.to_string()),
+                _ => ToolChoice::Raw(value),
+            },
+        };
+        Ok(choice)
+    }
 }
 
 // Custom Serialize implementation for APIRequest
@@ -98,9 +243,11 @@ impl Serialize for APIRequest {
             state.serialize_field("tools", &self.tools)?;
         }
 
-        // Serialize "tool_choice" only if it is not equal to the string "none"
-        if self.tool_choice != serde_json::Value::String("none".to_string()) {
-            state.serialize_field("tool_choice", &self.tool_choice)?;
+        // Serialize "tool_choice" only if it is set and there are tools to choose from
+        if let Some(tool_choice) = &self.tool_choice {
+            if !self.tools.is_empty() {
+                state.serialize_field("tool_choice", tool_choice)?;
+            }
         }
 
         // Serialize optional fields if they are present
@@ -111,7 +258,10 @@ impl Serialize for APIRequest {
             state.serialize_field("temperature", temperature)?;
         }
         if let Some(max_completion_tokens) = &self.max_completion_tokens {
-            state.serialize_field("max_completion_tokens", max_completion_tokens)?;
+            match self.token_param {
+                TokenParam::MaxCompletionTokens => state.serialize_field("max_completion_tokens", max_completion_tokens)?,
+                TokenParam::MaxTokens => state.serialize_field("max_tokens", max_completion_tokens)?,
+            }
         }
         if let Some(top_p) = &self.top_p {
             state.serialize_field("top_p", top_p)?;
@@ -122,6 +272,45 @@ impl Serialize for APIRequest {
         if let Some(presence_penalty) = &self.presence_penalty {
             state.serialize_field("presence_penalty", presence_penalty)?;
         }
+        if let Some(frequency_penalty) = &self.frequency_penalty {
+            state.serialize_field("frequency_penalty", frequency_penalty)?;
+        }
+        if let Some(stop) = &self.stop {
+            state.serialize_field("stop", stop)?;
+        }
+        if let Some(logit_bias) = &self.logit_bias {
+            state.serialize_field("logit_bias", logit_bias)?;
+        }
+        if let Some(n) = &self.n {
+            state.serialize_field("n", n)?;
+        }
+        if let Some(user) = &self.user {
+            state.serialize_field("user", user)?;
+        }
+        if let Some(logprobs) = &self.logprobs {
+            state.serialize_field("logprobs", logprobs)?;
+        }
+        if let Some(top_logprobs) = &self.top_logprobs {
+            state.serialize_field("top_logprobs", top_logprobs)?;
+        }
+        if let Some(web_search_options) = &self.web_search_options {
+            state.serialize_field("web_search_options", web_search_options)?;
+        }
+        // Serialize "response_format" only if it is not plain text (the API default)
+        if let Some(response_format) = &self.response_format {
+            if !matches!(response_format, ResponseFormat::Text) {
+                state.serialize_field("response_format", response_format)?;
+            }
+        }
+        if let Some(stream) = &self.stream {
+            state.serialize_field("stream", stream)?;
+        }
+        // The API rejects "stream_options" on non-streaming requests
+        if let Some(stream_options) = &self.stream_options {
+            if self.stream == Some(true) {
+                state.serialize_field("stream_options", stream_options)?;
+            }
+        }
 
         state.end()
     }
@@ -131,8 +320,11 @@ impl Serialize for APIRequest {
 #[derive(Debug, Deserialize, Clone)]
 pub struct APIResponse {
     /// Unique identifier for the API response
+    /// (empty for error responses)
+    #[serde(default)]
     pub id: String,
     /// IDK
+    #[serde(default)]
     pub object: String,
     /// Model name used in the response
     pub model: Option<String>,
@@ -146,20 +338,86 @@ pub struct APIResponse {
     pub created: Option<u64>,
 }
 
+/// API Response chunk structure received while streaming
+#[derive(Debug, Deserialize, Clone)]
+pub struct APIStreamResponse {
+    /// Unique identifier for the API response (shared by all chunks)
+    #[serde(default)]
+    pub id: String,
+    /// IDK
+    #[serde(default)]
+    pub object: String,
+    /// Model name used in the response
+    pub model: Option<String>,
+    /// Array of incremental choices
+    pub choices: Option<Vec<StreamChoice>>,
+    /// Error information if the request failed
+    pub error: Option<APIError>,
+    /// Information regarding token usage
+    pub usage: Option<APIUsage>,
+    /// Timestamp of when the response was created
+    pub created: Option<u64>,
+}
+
 /// API Error information structure
+///
+/// # Example
+///
+/// ```
+/// # use call_agent::chat::{api::APIErrorResponse, err::ClientError};
+/// let body = r#"{
+///     "error": {
+///         "message": "Incorrect API key provided: sk-abc. You can find your API key at https://platform.openai.com/account/api-keys.",
+///         "type": "invalid_request_error",
+///         "param": null,
+///         "code": "invalid_api_key"
+///     }
+/// }"#;
+/// let err: ClientError = serde_json::from_str::<APIErrorResponse>(body).unwrap().error.unwrap().into();
+/// assert!(matches!(err, ClientError::ApiError { code: Some(ref code), .. } if code == "invalid_api_key"));
+///
+/// // Numeric codes are kept as their string form.
+/// let body = r#"{"error": {"message": "Rate limit reached", "type": "requests", "code": 429}}"#;
+/// let err = serde_json::from_str::<APIErrorResponse>(body).unwrap().error.unwrap();
+/// assert_eq!(err.code.as_deref(), Some("429"));
+/// ```
 #[derive(Debug, Deserialize, Clone)]
 pub struct APIError {
     /// Error message text
     pub message: String,
     /// Error type (renamed from "type" to avoid keyword conflict)
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default)]
     pub err_type: String,
-    /// Error code number
-    pub code: i32,
+    /// Error code (e.g. "invalid_api_key", "model_not_found")
+    /// Numeric codes are converted to their string form
+    #[serde(default, deserialize_with = "deserialize_error_code")]
+    pub code: Option<String>,
+}
+
+fn deserialize_error_code<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: serde_json::Value = Deserialize::deserialize(deserializer)?;
+    Ok(match value {
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::String(s) => Some(s),
+        _ => None,
+    })
+}
+
+impl From<APIError> for ClientError {
+    fn from(err: APIError) -> Self {
+        ClientError::ApiError {
+            message: err.message,
+            err_type: err.err_type,
+            code: err.code,
+        }
+    }
 }
 
 /// API Usage information detailing token counts
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct APIUsage {
     /// Number of tokens used in the prompt
     pub prompt_tokens: Option<u64>,
@@ -167,6 +425,112 @@ pub struct APIUsage {
     pub completion_tokens: Option<u64>,
     /// Total number of tokens used (prompt + response)
     pub total_tokens: Option<u64>,
+    /// Breakdown of the prompt tokens
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+    /// Breakdown of the completion tokens
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+/// Breakdown of the prompt tokens
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct PromptTokensDetails {
+    /// Number of prompt tokens read from the prompt cache
+    pub cached_tokens: Option<u64>,
+}
+
+/// Breakdown of the completion tokens
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct CompletionTokensDetails {
+    /// Number of tokens the model used for reasoning (included in `completion_tokens`)
+    pub reasoning_tokens: Option<u64>,
+    /// Number of tokens of the predicted output that appeared in the completion
+    pub accepted_prediction_tokens: Option<u64>,
+    /// Number of tokens of the predicted output that did not appear in the completion
+    pub rejected_prediction_tokens: Option<u64>,
+}
+
+/// Sum two token counts, treating a missing count as zero unless both are missing.
+fn sum_tokens(a: Option<u64>, b: Option<u64>) -> Option<u64> {
+    match (a, b) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+    }
+}
+
+impl APIUsage {
+    /// Add the token counts of another usage to this one.
+    ///
+    /// A count missing on one side is treated as zero.
+    pub fn add(&mut self, other: &APIUsage) {
+        self.prompt_tokens = sum_tokens(self.prompt_tokens, other.prompt_tokens);
+        self.completion_tokens = sum_tokens(self.completion_tokens, other.completion_tokens);
+        self.total_tokens = sum_tokens(self.total_tokens, other.total_tokens);
+        if let Some(details) = &other.prompt_tokens_details {
+            let total = self.prompt_tokens_details.get_or_insert_with(Default::default);
+            total.cached_tokens = sum_tokens(total.cached_tokens, details.cached_tokens);
+        }
+        if let Some(details) = &other.completion_tokens_details {
+            let total = self.completion_tokens_details.get_or_insert_with(Default::default);
+            total.reasoning_tokens = sum_tokens(total.reasoning_tokens, details.reasoning_tokens);
+            total.accepted_prediction_tokens = sum_tokens(total.accepted_prediction_tokens, details.accepted_prediction_tokens);
+            total.rejected_prediction_tokens = sum_tokens(total.rejected_prediction_tokens, details.rejected_prediction_tokens);
+        }
+    }
+}
+
+/// Output format requested from the model
+///
+/// `Text` is the API default and is omitted from the request.
+#[derive(Debug, Clone, Deserialize)]
+pub enum ResponseFormat {
+    /// Plain text output (default)
+    Text,
+    /// Any valid JSON object ("JSON mode")
+    JsonObject,
+    /// JSON constrained to the given JSON schema (structured outputs)
+    JsonSchema {
+        /// Name of the response format
+        /// should the name matches the pattern '^[a-zA-Z0-9_-]+$'
+        name: String,
+        /// JSON schema the output must follow
+        schema: serde_json::Value,
+        /// Strictly follow the schema
+        /// default: false
+        strict: Option<bool>,
+    },
+}
+
+impl Serialize for ResponseFormat {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            ResponseFormat::Text => {
+                let mut state = serializer.serialize_struct("ResponseFormat", 1)?;
+                state.serialize_field("type", "text")?;
+                state.end()
+            }
+            ResponseFormat::JsonObject => {
+                let mut state = serializer.serialize_struct("ResponseFormat", 1)?;
+                state.serialize_field("type", "json_object")?;
+                state.end()
+            }
+            ResponseFormat::JsonSchema { name, schema, strict } => {
+                let mut json_schema = serde_json::Map::new();
+                json_schema.insert("name".to_string(), serde_json::Value::String(name.clone()));
+                json_schema.insert("schema".to_string(), schema.clone());
+                if let Some(strict) = strict {
+                    json_schema.insert("strict".to_string(), serde_json::Value::Bool(*strict));
+                }
+
+                let mut state = serializer.serialize_struct("ResponseFormat", 2)?;
+                state.serialize_field("type", "json_schema")?;
+                state.serialize_field("json_schema", &json_schema)?;
+                state.end()
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -175,6 +539,7 @@ pub struct WebSearchOptions {
     /// - "low"
     /// - "medium"
     /// - "high"
+    ///
     /// default: "medium"
     pub search_context_size: Option<String>,
     pub user_location: UserLocation,
@@ -240,4 +605,123 @@ impl Serialize for UserLocation {
         state.serialize_field("approximate", &approximate)?;
         state.end()
     }
-}
\ No newline at end of file
+}
+
+/// Error body returned by the API on failure
+#[derive(Debug, Deserialize, Clone)]
+pub struct APIErrorResponse {
+    /// Error information
+    pub error: Option<APIError>,
+}
+
+/// Request structure for the embeddings API
+#[derive(Debug, Serialize, Clone)]
+pub struct EmbeddingRequest {
+    /// Model name used for the embeddings
+    pub model: String,
+    /// Input texts to embed
+    pub input: Vec<String>,
+}
+
+/// Response structure from the embeddings API
+#[derive(Debug, Deserialize, Clone)]
+pub struct EmbeddingResponse {
+    /// Embeddings of each input
+    pub data: Vec<EmbeddingData>,
+    /// Model name used in the response
+    pub model: Option<String>,
+    /// Information regarding token usage
+    pub usage: Option<APIUsage>,
+}
+
+/// Embedding of a single input
+#[derive(Debug, Deserialize, Clone)]
+pub struct EmbeddingData {
+    /// Index of the input this embedding belongs to
+    pub index: usize,
+    /// Embedding vector
+    pub embedding: Vec<f32>,
+}
+
+/// Request structure for the moderations API
+#[derive(Debug, Serialize, Clone)]
+pub struct ModerationRequest {
+    /// Input text to classify
+    pub input: String,
+}
+
+/// Response structure from the moderations API
+#[derive(Debug, Deserialize, Clone)]
+pub struct ModerationResponse {
+    /// Model name used in the response
+    pub model: Option<String>,
+    /// Classification results, one per input
+    pub results: Vec<ModerationResult>,
+}
+
+/// Classification result of a single input
+#[derive(Debug, Deserialize, Clone)]
+pub struct ModerationResult {
+    /// Whether the input violates any category
+    pub flagged: bool,
+    /// Whether each category is violated
+    #[serde(default)]
+    pub categories: HashMap<String, bool>,
+    /// Score of each category (0.0..1.0)
+    #[serde(default)]
+    pub category_scores: HashMap<String, f64>,
+}
+
+/// Format of the images returned by the image generation API
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageResponseFormat {
+    /// URL of the generated image (valid for a limited time)
+    Url,
+    /// Base64-encoded PNG image
+    B64Json,
+}
+
+/// Request structure for the image generation API
+#[derive(Debug, Serialize, Clone)]
+pub struct ImageGenerationRequest {
+    /// Image model (e.g. "dall-e-3", "gpt-image-1")
+    /// None uses the API default (dall-e-2)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Description of the image to generate
+    pub prompt: String,
+    /// Size of the image (e.g. "1024x1024")
+    pub size: String,
+    /// Number of images to generate
+    pub n: u32,
+    /// Format of the returned images
+    pub response_format: ImageResponseFormat,
+}
+
+/// Response structure from the image generation API
+#[derive(Debug, Deserialize, Clone)]
+pub struct ImageGenerationResponse {
+    /// Generated images
+    pub data: Vec<ImageData>,
+    /// Timestamp of when the response was created
+    pub created: Option<u64>,
+}
+
+/// A single generated image
+#[derive(Debug, Deserialize, Clone)]
+pub struct ImageData {
+    /// URL of the image, if requested with `ImageResponseFormat::Url`
+    pub url: Option<String>,
+    /// Base64-encoded image, if requested with `ImageResponseFormat::B64Json`
+    pub b64_json: Option<String>,
+    /// Prompt actually used by the model, if it was revised
+    pub revised_prompt: Option<String>,
+}
+
+/// Response structure from the audio transcription API
+#[derive(Debug, Deserialize, Clone)]
+pub struct TranscriptionResponse {
+    /// Transcribed text
+    pub text: String,
+}
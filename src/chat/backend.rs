@@ -0,0 +1,51 @@
+use std::collections::VecDeque;
+
+use async_trait::async_trait;
+
+use super::{
+    api::ToolChoice,
+    client::{APIResult, ModelConfig},
+    err::ClientError,
+    function::ToolDef,
+    prompt::Message,
+};
+
+/// A chat completion provider.
+///
+/// `OpenAIClientState` sends every request through a backend, so another provider
+/// (e.g. a local server or a different API) can be used by implementing this trait
+/// and passing it to `OpenAIClient::create_prompt_with_backend`.
+/// `OpenAIClient` is the default implementation.
+///
+/// The tool registry and the default model configuration stay on `OpenAIClient`;
+/// the backend only builds the request and parses the response.
+///
+/// # Limitations
+///
+/// - `OpenAIClientState<B>` still holds an `OpenAIClient` to keep the tools and the model
+///   configuration, so one has to be created even when every request goes through another
+///   backend. Its endpoint and API key are not used then.
+/// - Streaming (`generate_stream`) is only available on `OpenAIClientState<OpenAIClient>`,
+///   since this trait has no streaming method.
+#[async_trait]
+pub trait ChatBackend: Send + Sync {
+    /// Send the prompt and return the completion.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The list of messages.
+    /// * `tools` - The definitions of the enabled tools.
+    /// * `config` - The model configuration.
+    /// * `tool_choice` - Indicates function call mode; `None` leaves it to the provider default.
+    ///
+    /// # Returns
+    ///
+    /// An APIResult on success or a ClientError on failure.
+    async fn complete(
+        &self,
+        prompt: &VecDeque<Message>,
+        tools: &[ToolDef],
+        config: &ModelConfig,
+        tool_choice: Option<&ToolChoice>,
+    ) -> Result<APIResult, ClientError>;
+}
@@ -1,16 +1,40 @@
-use std::{collections::{HashMap, VecDeque}, sync::Arc};
+use std::{collections::{HashMap, VecDeque}, panic::AssertUnwindSafe, sync::Arc, time::Duration};
 
-use reqwest::{Client, Response};
+use futures::{future::join_all, FutureExt};
+use reqwest::{header::{HeaderMap, HeaderName, HeaderValue}, multipart::{Form, Part}, Client, RequestBuilder, Response, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use async_trait::async_trait;
+use tokio_util::sync::CancellationToken;
 
-use crate::chat::api::WebSearchOptions;
+use crate::chat::api::{ResponseFormat, StreamOptions, TokenParam, WebSearchOptions};
 
 use super::{
-    api::{APIRequest, APIResponse, APIResponseHeaders},
+    api::{
+        APIErrorResponse, APIRequest, APIResponse, APIResponseHeaders, APIUsage, EmbeddingRequest, EmbeddingResponse,
+        ImageGenerationRequest, ImageGenerationResponse, ImageResponseFormat, ModerationRequest, ModerationResponse,
+        ModerationResult, ToolChoice, TranscriptionResponse,
+    },
+    backend::ChatBackend,
     err::ClientError,
-    function::{FunctionCall, FunctionDef, Tool, ToolDef},
-    prompt::{Message, MessageContext},
+    function::{AsyncTool, FunctionCall, FunctionDef, Tool, ToolDef},
+    prompt::{Annotation, Choice, ChoiceLogprobs, FinishReason, Message, MessageContext, UrlCitation},
+    stream::ChatStream,
+    tokenizer,
 };
 
+/// Default total timeout of a request.
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+/// Default path of the chat completions API.
+const DEFAULT_COMPLETIONS_PATH: &str = "chat/completions";
+
+/// A registered tool and how it is run.
+#[derive(Clone)]
+struct RegisteredTool {
+    tool: Arc<dyn AsyncTool + Send + Sync>,
+    /// Registered with `def_tool`, so `run` blocks the calling thread.
+    blocking: bool,
+}
+
 /// Main client structure for interacting with the OpenAI API.
 #[derive(Clone)]
 pub struct OpenAIClient {
@@ -21,42 +45,520 @@ pub struct OpenAIClient {
     /// Optional API key
     pub api_key: Option<String>,
     /// Registered tools: key is the tool name, value is a tuple (tool, is_enabled)
-    pub tools: HashMap<String, (Arc<dyn Tool + Send + Sync>, bool)>,
+    /// Managed with `def_tool`, `def_async_tool`, `switch_tool`, `remove_tool` and `clear_tools`.
+    tools: HashMap<String, (RegisteredTool, bool)>,
     /// Configuration for the model request.
     pub model_config: Option<ModelConfig>,
+    /// Retry policy for rate limited or failed requests.
+    /// None disables retrying.
+    pub retry_config: Option<RetryConfig>,
+    /// Total timeout of a request, including reading the response body.
+    /// This also bounds the whole duration of a streamed response.
+    /// default: 120 seconds
+    /// None means no timeout.
+    /// Only applied by rebuilding `client`, so it is set with `with_timeout`.
+    timeout: Option<Duration>,
+    /// Timeout for establishing a connection.
+    /// None means no timeout.
+    /// Set with `with_connect_timeout`.
+    connect_timeout: Option<Duration>,
+    /// Accept invalid TLS certificates (see `danger_accept_invalid_certs`).
+    /// default: false
+    accept_invalid_certs: bool,
+    /// Optional organization ID sent as the `OpenAI-Organization` header
+    pub organization: Option<String>,
+    /// Optional project ID sent as the `OpenAI-Project` header
+    pub project: Option<String>,
+    /// Custom headers added to every request.
+    /// They are applied last, so they override the built-in headers with the same name.
+    pub headers: HashMap<String, String>,
+    /// Validate the arguments of tool calls against the schema of the tool before running it.
+    /// Invalid arguments are reported back to the model as the tool result instead.
+    /// default: false
+    pub validate_tool_args: bool,
+    /// Azure OpenAI API version.
+    /// When set, requests use the `api-version` query parameter and the `api-key` header
+    /// instead of `Bearer` authorization.
+    pub azure_api_version: Option<String>,
+    /// Include the request and response bodies in the `tracing` events.
+    /// They contain the conversation, so they are not recorded by default.
+    /// Only has an effect with the `tracing` feature.
+    /// default: false
+    pub trace_content: bool,
+    /// Return `ClientError::Truncated` from the `generate*` methods when the output was
+    /// cut off by the maximum token limit, instead of treating it as a complete answer.
+    /// default: false
+    pub error_on_truncation: bool,
+    /// Path of the chat completions API, relative to the endpoint.
+    /// default: "chat/completions"
+    pub completions_path: String,
+    /// Replace the invalid characters of message names with `_` instead of
+    /// failing with `ClientError::InvalidInput`.
+    /// default: false
+    pub sanitize_names: bool,
+    /// Send developer messages with the `system` role, for models and proxies
+    /// that reject the `developer` role.
+    /// default: false
+    pub downgrade_developer_to_system: bool,
 }
 
 /// Configuration for the model request.
-#[derive(Debug, Clone)]
+///
+/// `Default` leaves the model empty and every option `None`,
+/// so it can be combined with struct update syntax.
+#[derive(Debug, Clone, Default)]
 pub struct ModelConfig {
     /// Model name.
     pub model: String,
     /// Optional model name.
     pub model_name: Option<String>,
     /// Top-p sampling parameter.
+    /// Range: 0.0..1.0
     pub top_p: Option<f64>,
     /// Specifies whether to perform parallel ToolCalls.
     /// default: true
     pub parallel_tool_calls: Option<bool>,
     /// Specifies the diversity of tokens generated by the model.
+    /// Range: 0.0..2.0
     pub temperature: Option<f64>,
     /// Specifies the maximum number of tokens generated by the model.
     pub max_completion_tokens: Option<u64>,
+    /// Field `max_completion_tokens` is sent as.
+    /// Set `TokenParam::MaxTokens` for models that only accept `max_tokens`.
+    /// default: `TokenParam::MaxCompletionTokens`
+    pub token_param: Option<TokenParam>,
     /// Specifies the level of effort for reasoning in the inference model:
     /// - "low": Low effort
     /// - "medium": Medium effort
     /// - "high": High effort
+    ///
     /// default: "medium"
     pub reasoning_effort: Option<String>,
     /// Specifies whether to apply a presence penalty to the model.
-    /// Range: 2.0..-2.0
+    /// Range: -2.0..2.0
     pub presence_penalty: Option<f64>,
+    /// Specifies whether to apply a frequency penalty to the model.
+    /// Positive values discourage repeating the same tokens.
+    /// Range: -2.0..2.0
+    pub frequency_penalty: Option<f64>,
+    /// Sequences where the model stops generating further tokens.
+    /// Up to 4 sequences.
+    pub stop: Option<Vec<String>>,
+    /// Modifies the likelihood of specified tokens appearing in the output.
+    /// Maps token ids to a bias value in -100..100; -100 bans a token and 100 forces it.
+    /// Token ids are specific to the tokenizer of the model.
+    pub logit_bias: Option<HashMap<u32, f64>>,
+    /// Number of completions to generate for each request.
+    /// default: 1
+    pub n: Option<u32>,
+    /// A stable identifier of the end user, used by the API for abuse monitoring.
+    pub user: Option<String>,
+    /// Whether to return log probabilities of the output tokens.
+    pub logprobs: Option<bool>,
+    /// Number of most likely alternatives to return at each token position (0..20).
+    /// Requires `logprobs` to be true.
+    pub top_logprobs: Option<u8>,
     /// Strictly structured
     /// default: false
-    /// Forced disabled in parallel ToolCalls
+    /// Forced disabled in parallel ToolCalls; set `parallel_tool_calls` to false to use it
+    pub strict: Option<bool>,
+    /// Options for performing web search with available models.
+    pub web_search_options: Option<WebSearchOptions>,
+    /// Specifies the format that the model must output.
+    /// - `ResponseFormat::JsonObject`: JSON mode
+    /// - `ResponseFormat::JsonSchema`: Structured outputs following a JSON schema
+    pub response_format: Option<ResponseFormat>,
+    /// Options for streaming requests, e.g. `include_usage` to receive the token usage.
+    /// Ignored by non-streaming requests.
+    pub stream_options: Option<StreamOptions>,
+}
+
+/// Retry policy applied when the API returns HTTP 429 or a 5xx status,
+/// or when the request fails with a transient network error.
+/// Other 4xx responses are never retried.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Maximum number of retries after the first attempt.
+    pub max_retries: u32,
+    /// Base delay of the exponential backoff.
+    /// The n-th retry waits `base_delay * 2^n`.
+    pub base_delay: Duration,
+    /// Upper bound of the delay, including the one given by `Retry-After`.
+    pub max_delay: Duration,
+    /// Wait for the duration given by the `Retry-After` header when present.
+    /// If the server asks to wait longer than `max_delay`, the request is not retried
+    /// and `ClientError::RateLimited` is returned right away.
+    pub respect_retry_after: bool,
+    /// Add a random delay of up to half the backoff delay,
+    /// so that many clients do not retry at the same moment.
+    pub jitter: bool,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(30),
+            respect_retry_after: true,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Compute the delay before the given retry attempt (0-based).
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - The number of retries already performed.
+    /// * `retry_after` - The `Retry-After` header value in seconds, if any.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::time::Duration;
+    /// # use call_agent::chat::client::RetryConfig;
+    /// let retry = RetryConfig { jitter: false, ..Default::default() };
+    /// assert_eq!(retry.delay(2, None), Duration::from_secs(1));
+    /// assert_eq!(retry.delay(0, Some(5)), Duration::from_secs(5));
+    /// assert_eq!(retry.delay(0, Some(86400)), retry.max_delay);
+    /// ```
+    pub fn delay(&self, attempt: u32, retry_after: Option<u64>) -> Duration {
+        if self.respect_retry_after {
+            if let Some(secs) = retry_after {
+                return Duration::from_secs(secs).min(self.max_delay);
+            }
+        }
+        let delay = self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        if self.jitter {
+            let max_jitter = delay.as_millis() as u64 / 2;
+            delay + Duration::from_millis(fastrand::u64(0..=max_jitter))
+        } else {
+            delay
+        }
+    }
+}
+
+impl ModelConfig {
+    /// Create a model configuration with only the model set.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model name.
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Create a builder for a model configuration.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use call_agent::chat::client::ModelConfig;
+    /// let config = ModelConfig::builder()
+    ///     .model("gpt-4o-mini")
+    ///     .temperature(0.8)
+    ///     .max_completion_tokens(1000)
+    ///     .build();
+    /// ```
+    pub fn builder() -> ModelConfigBuilder {
+        ModelConfigBuilder::default()
+    }
+
+    /// Create a copy of this configuration with the fields set in `overrides` replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `overrides` - The fields to replace.
+    pub fn with_overrides(&self, overrides: &ModelConfigOverride) -> ModelConfig {
+        ModelConfig {
+            model: overrides.model.clone().unwrap_or_else(|| self.model.clone()),
+            model_name: overrides.model_name.clone().or_else(|| self.model_name.clone()),
+            top_p: overrides.top_p.or(self.top_p),
+            parallel_tool_calls: overrides.parallel_tool_calls.or(self.parallel_tool_calls),
+            temperature: overrides.temperature.or(self.temperature),
+            max_completion_tokens: overrides.max_completion_tokens.or(self.max_completion_tokens),
+            token_param: overrides.token_param.or(self.token_param),
+            reasoning_effort: overrides.reasoning_effort.clone().or_else(|| self.reasoning_effort.clone()),
+            presence_penalty: overrides.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: overrides.frequency_penalty.or(self.frequency_penalty),
+            stop: overrides.stop.clone().or_else(|| self.stop.clone()),
+            logit_bias: overrides.logit_bias.clone().or_else(|| self.logit_bias.clone()),
+            n: overrides.n.or(self.n),
+            user: overrides.user.clone().or_else(|| self.user.clone()),
+            logprobs: overrides.logprobs.or(self.logprobs),
+            top_logprobs: overrides.top_logprobs.or(self.top_logprobs),
+            strict: overrides.strict.or(self.strict),
+            web_search_options: overrides.web_search_options.clone().or_else(|| self.web_search_options.clone()),
+            response_format: overrides.response_format.clone().or_else(|| self.response_format.clone()),
+            stream_options: overrides.stream_options.clone().or_else(|| self.stream_options.clone()),
+        }
+    }
+
+    /// Whether tool definitions are sent with `strict: true`.
+    ///
+    /// `strict` is forced off while parallel tool calls are enabled (the default),
+    /// since the API rejects the combination.
+    pub fn tool_strict(&self) -> bool {
+        self.strict.unwrap_or(false) && self.parallel_tool_calls == Some(false)
+    }
+
+    /// Check the configuration for values the API would reject.
+    ///
+    /// # Returns
+    ///
+    /// `ClientError::InvalidInput` describing the first invalid value found.
+    pub fn validate(&self) -> Result<(), ClientError> {
+        let ranges = [
+            ("temperature", self.temperature, 0.0..=2.0),
+            ("top_p", self.top_p, 0.0..=1.0),
+            ("presence_penalty", self.presence_penalty, -2.0..=2.0),
+            ("frequency_penalty", self.frequency_penalty, -2.0..=2.0),
+        ];
+        for (name, value, range) in ranges {
+            if let Some(value) = value {
+                if !range.contains(&value) {
+                    return Err(ClientError::InvalidInput(format!(
+                        "{} must be in {}..{}, got {}",
+                        name, range.start(), range.end(), value
+                    )));
+                }
+            }
+        }
+        if let Some(stop) = &self.stop {
+            if stop.len() > 4 {
+                return Err(ClientError::InvalidInput(format!(
+                    "stop accepts up to 4 sequences, got {}",
+                    stop.len()
+                )));
+            }
+        }
+        if let Some(top_logprobs) = self.top_logprobs {
+            if top_logprobs > 20 {
+                return Err(ClientError::InvalidInput(format!(
+                    "top_logprobs must be in 0..20, got {}",
+                    top_logprobs
+                )));
+            }
+        }
+        if let Some(logit_bias) = &self.logit_bias {
+            if let Some((token, bias)) = logit_bias.iter().find(|(_, bias)| !(-100.0..=100.0).contains(*bias)) {
+                return Err(ClientError::InvalidInput(format!(
+                    "logit_bias for token {} must be in -100..100, got {}",
+                    token, bias
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builder for `ModelConfig`.
+///
+/// Every option left unset is `None`.
+#[derive(Debug, Clone, Default)]
+pub struct ModelConfigBuilder {
+    config: ModelConfig,
+}
+
+impl ModelConfigBuilder {
+    /// Set the model name.
+    pub fn model(mut self, model: &str) -> Self {
+        self.config.model = model.to_string();
+        self
+    }
+
+    /// Set the name attached to the assistant messages.
+    pub fn model_name(mut self, model_name: &str) -> Self {
+        self.config.model_name = Some(model_name.to_string());
+        self
+    }
+
+    /// Set the top-p sampling parameter.
+    pub fn top_p(mut self, top_p: f64) -> Self {
+        self.config.top_p = Some(top_p);
+        self
+    }
+
+    /// Set whether to perform parallel ToolCalls.
+    pub fn parallel_tool_calls(mut self, parallel_tool_calls: bool) -> Self {
+        self.config.parallel_tool_calls = Some(parallel_tool_calls);
+        self
+    }
+
+    /// Set the sampling temperature.
+    pub fn temperature(mut self, temperature: f64) -> Self {
+        self.config.temperature = Some(temperature);
+        self
+    }
+
+    /// Set the maximum number of tokens generated by the model.
+    pub fn max_completion_tokens(mut self, max_completion_tokens: u64) -> Self {
+        self.config.max_completion_tokens = Some(max_completion_tokens);
+        self
+    }
+
+    /// Set the field the maximum number of tokens is sent as.
+    pub fn token_param(mut self, token_param: TokenParam) -> Self {
+        self.config.token_param = Some(token_param);
+        self
+    }
+
+    /// Set the level of effort for reasoning ("low", "medium" or "high").
+    pub fn reasoning_effort(mut self, reasoning_effort: &str) -> Self {
+        self.config.reasoning_effort = Some(reasoning_effort.to_string());
+        self
+    }
+
+    /// Set the presence penalty.
+    pub fn presence_penalty(mut self, presence_penalty: f64) -> Self {
+        self.config.presence_penalty = Some(presence_penalty);
+        self
+    }
+
+    /// Set the frequency penalty.
+    pub fn frequency_penalty(mut self, frequency_penalty: f64) -> Self {
+        self.config.frequency_penalty = Some(frequency_penalty);
+        self
+    }
+
+    /// Set the stop sequences.
+    pub fn stop(mut self, stop: Vec<String>) -> Self {
+        self.config.stop = Some(stop);
+        self
+    }
+
+    /// Set the logit bias.
+    pub fn logit_bias(mut self, logit_bias: HashMap<u32, f64>) -> Self {
+        self.config.logit_bias = Some(logit_bias);
+        self
+    }
+
+    /// Set the number of completions to generate.
+    pub fn n(mut self, n: u32) -> Self {
+        self.config.n = Some(n);
+        self
+    }
+
+    /// Set the end user identifier.
+    pub fn user(mut self, user: &str) -> Self {
+        self.config.user = Some(user.to_string());
+        self
+    }
+
+    /// Set whether to return log probabilities.
+    pub fn logprobs(mut self, logprobs: bool) -> Self {
+        self.config.logprobs = Some(logprobs);
+        self
+    }
+
+    /// Set the number of most likely alternatives returned at each token position.
+    pub fn top_logprobs(mut self, top_logprobs: u8) -> Self {
+        self.config.top_logprobs = Some(top_logprobs);
+        self
+    }
+
+    /// Set whether tool definitions are strictly structured.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.config.strict = Some(strict);
+        self
+    }
+
+    /// Set the web search options.
+    pub fn web_search_options(mut self, web_search_options: WebSearchOptions) -> Self {
+        self.config.web_search_options = Some(web_search_options);
+        self
+    }
+
+    /// Set the output format.
+    pub fn response_format(mut self, response_format: ResponseFormat) -> Self {
+        self.config.response_format = Some(response_format);
+        self
+    }
+
+    /// Set the options for streaming requests.
+    pub fn stream_options(mut self, stream_options: StreamOptions) -> Self {
+        self.config.stream_options = Some(stream_options);
+        self
+    }
+
+    /// Build the model configuration.
+    pub fn build(self) -> ModelConfig {
+        self.config
+    }
+}
+
+/// Per-call changes to a `ModelConfig`.
+///
+/// Every field set to `Some` replaces the value of the base configuration,
+/// every `None` field keeps it. See `ModelConfig::with_overrides`.
+///
+/// # Example
+///
+/// ```
+/// # use call_agent::chat::client::{ModelConfig, ModelConfigOverride};
+/// let base = ModelConfig::builder().model("gpt-4o-mini").temperature(0.2).max_completion_tokens(1000).build();
+/// let config = base.with_overrides(&ModelConfigOverride {
+///     temperature: Some(1.2),
+///     ..Default::default()
+/// });
+/// assert_eq!(config.temperature, Some(1.2));
+/// assert_eq!(config.max_completion_tokens, Some(1000));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ModelConfigOverride {
+    /// Model name.
+    pub model: Option<String>,
+    /// Name attached to the assistant messages.
+    pub model_name: Option<String>,
+    /// Top-p sampling parameter.
+    /// Range: 0.0..1.0
+    pub top_p: Option<f64>,
+    /// Specifies whether to perform parallel ToolCalls.
+    pub parallel_tool_calls: Option<bool>,
+    /// Specifies the diversity of tokens generated by the model.
+    /// Range: 0.0..2.0
+    pub temperature: Option<f64>,
+    /// Specifies the maximum number of tokens generated by the model.
+    pub max_completion_tokens: Option<u64>,
+    /// Field `max_completion_tokens` is sent as.
+    pub token_param: Option<TokenParam>,
+    /// Specifies the level of effort for reasoning ("low", "medium" or "high").
+    pub reasoning_effort: Option<String>,
+    /// Specifies whether to apply a presence penalty to the model.
+    /// Range: -2.0..2.0
+    pub presence_penalty: Option<f64>,
+    /// Specifies whether to apply a frequency penalty to the model.
+    /// Range: -2.0..2.0
+    pub frequency_penalty: Option<f64>,
+    /// Sequences where the model stops generating further tokens.
+    /// Up to 4 sequences.
+    pub stop: Option<Vec<String>>,
+    /// Modifies the likelihood of specified tokens appearing in the output.
+    pub logit_bias: Option<HashMap<u32, f64>>,
+    /// Number of completions to generate for each request.
+    pub n: Option<u32>,
+    /// A stable identifier of the end user, used by the API for abuse monitoring.
+    pub user: Option<String>,
+    /// Whether to return log probabilities of the output tokens.
+    pub logprobs: Option<bool>,
+    /// Number of most likely alternatives to return at each token position (0..20).
+    pub top_logprobs: Option<u8>,
+    /// Strictly structured
+    /// Forced disabled in parallel ToolCalls; set `parallel_tool_calls` to false to use it
     pub strict: Option<bool>,
     /// Options for performing web search with available models.
     pub web_search_options: Option<WebSearchOptions>,
+    /// Specifies the format that the model must output.
+    pub response_format: Option<ResponseFormat>,
+    /// Options for streaming requests. Ignored by non-streaming requests.
+    pub stream_options: Option<StreamOptions>,
 }
 
 /// Contains the API response and its headers.
@@ -68,6 +570,56 @@ pub struct APIResult {
     pub headers: APIResponseHeaders,
 }
 
+impl APIResult {
+    /// The first choice of the response.
+    ///
+    /// # Returns
+    ///
+    /// `ClientError::NoChoices` if `choices` is empty, or `ClientError::InvalidResponse` if it is missing.
+    pub fn first_choice(&self) -> Result<&Choice, ClientError> {
+        let choices = self.response.choices.as_ref().ok_or(ClientError::InvalidResponse)?;
+        choices.first().ok_or_else(|| ClientError::NoChoices {
+            object: self.response.object.clone(),
+            model: self.response.model.clone(),
+        })
+    }
+
+    /// The reason the model stopped generating the first choice.
+    ///
+    /// `FinishReason::Length` means the output was cut off by `max_completion_tokens`.
+    pub fn finish_reason(&self) -> Option<&FinishReason> {
+        self.response
+            .choices
+            .as_ref()
+            .and_then(|choices| choices.first())
+            .map(|choice| &choice.finish_reason)
+    }
+
+    /// Log probabilities of the first choice, if requested with `ModelConfig::logprobs`.
+    pub fn logprobs(&self) -> Option<&ChoiceLogprobs> {
+        self.response
+            .choices
+            .as_ref()
+            .and_then(|choices| choices.first())
+            .and_then(|choice| choice.logprobs.as_ref())
+    }
+
+    /// URL citations of the first choice, returned by web search models.
+    pub fn url_citations(&self) -> Vec<&UrlCitation> {
+        self.response
+            .choices
+            .iter()
+            .flatten()
+            .take(1)
+            .flat_map(|choice| choice.message.annotations.iter().flatten())
+            .filter_map(|annotation| match annotation {
+                Annotation::UrlCitation { url_citation } => Some(url_citation),
+                Annotation::Other => None,
+            })
+            .collect()
+    }
+}
+
 impl OpenAIClient {
     /// Create a new OpenAIClient.
     ///
@@ -76,110 +628,576 @@ impl OpenAIClient {
     /// * `end_point` - The endpoint of the OpenAI API.
     /// * `api_key` - Optional API key.
     pub fn new(end_point: &str, api_key: Option<&str>) -> Self {
+        let mut client = Self::with_client(Client::new(), end_point, api_key);
+        // If the client cannot be built, keep the default one without a timeout.
+        if let Ok(http) = build_http_client(Some(DEFAULT_TIMEOUT), None, false) {
+            client.client = http;
+            client.timeout = Some(DEFAULT_TIMEOUT);
+        }
+        client
+    }
+
+    /// Create a new OpenAIClient using an existing HTTP client.
+    ///
+    /// Timeouts, proxies and TLS settings of the given client are used as is.
+    /// Calling `with_timeout`, `with_connect_timeout` or `danger_accept_invalid_certs` afterwards
+    /// replaces it with a new client.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - The HTTP client.
+    /// * `end_point` - The endpoint of the OpenAI API.
+    /// * `api_key` - Optional API key.
+    pub fn with_client(client: Client, end_point: &str, api_key: Option<&str>) -> Self {
         Self {
-            client: Client::new(),
+            client,
             end_point: end_point.trim_end_matches('/').to_string(),
             api_key: api_key.map(|s| s.to_string()),
             tools: HashMap::new(),
             model_config: None,
+            retry_config: None,
+            timeout: None,
+            connect_timeout: None,
+            accept_invalid_certs: false,
+            organization: None,
+            project: None,
+            headers: HashMap::new(),
+            validate_tool_args: false,
+            trace_content: false,
+            azure_api_version: None,
+            error_on_truncation: false,
+            completions_path: DEFAULT_COMPLETIONS_PATH.to_string(),
+            sanitize_names: false,
+            downgrade_developer_to_system: false,
         }
     }
 
-    /// Set the default model configuration.
-    /// 
+    /// Create a new OpenAIClient for an Ollama server.
+    ///
+    /// No API key is used, so the `authorization` header is not sent.
+    ///
     /// # Arguments
-    /// 
-    /// * `model_config` - The model configuration.
-    pub fn set_model_config(&mut self, model_config: &ModelConfig) {
-        self.model_config = Some(model_config.clone());
+    ///
+    /// * `end_point` - The OpenAI compatible endpoint of the server (e.g. `http://localhost:11434/v1`).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use call_agent::chat::client::OpenAIClient;
+    /// let client = OpenAIClient::new_ollama("http://localhost:11434/v1");
+    /// assert!(client.api_key.is_none());
+    /// ```
+    pub fn new_ollama(end_point: &str) -> Self {
+        Self::new(end_point, None)
     }
 
-    /// Register a tool.
+    /// Create a new OpenAIClient for an Azure OpenAI deployment.
     ///
-    /// If a tool with the same name already exists, it will be overwritten.
+    /// Requests are sent to `{resource_endpoint}/openai/deployments/{deployment}/chat/completions`.
+    /// `ModelConfig::model` should be set to the deployment name.
     ///
     /// # Arguments
     ///
-    /// * `tool` - Reference-counted tool implementing the Tool trait.
-    pub fn def_tool<T: Tool + Send + Sync + 'static>(&mut self, tool: Arc<T>) {
-        self.tools
-            .insert(tool.def_name().to_string(), (tool, true));
+    /// * `resource_endpoint` - The endpoint of the Azure OpenAI resource (e.g. `https://{resource}.openai.azure.com`).
+    /// * `deployment` - The deployment name.
+    /// * `api_version` - The API version (e.g. `2024-10-21`).
+    /// * `api_key` - The API key of the resource.
+    pub fn new_azure(resource_endpoint: &str, deployment: &str, api_version: &str, api_key: &str) -> Self {
+        let end_point = format!(
+            "{}/openai/deployments/{}",
+            resource_endpoint.trim_end_matches('/'),
+            deployment
+        );
+        let mut client = Self::new(&end_point, Some(api_key));
+        client.azure_api_version = Some(api_version.to_string());
+        client
     }
 
-    /// List all registered tools.
+    /// Create a new OpenAIClient from environment variables.
+    ///
+    /// The API key is read from `OPENAI_API_KEY`, and the endpoint from `OPENAI_BASE_URL`
+    /// (default: `https://api.openai.com/v1/`).
     ///
     /// # Returns
     ///
-    /// A list of tuples containing (tool name, tool description, enabled flag).
-    pub fn list_tools(&self) -> Vec<(String, String, bool)> {
-        let mut tools = Vec::new();
-        for (tool_name, (tool, enable)) in self.tools.iter() {
-            tools.push((
-                tool_name.to_string(),
-                tool.def_description().to_string(),
-                *enable,
-            ));
-        }
-        tools
+    /// The client, or `ClientError::InvalidInput` if `OPENAI_API_KEY` is not set.
+    pub fn from_env() -> Result<Self, ClientError> {
+        let api_key = std::env::var("OPENAI_API_KEY")
+            .map_err(|_| ClientError::InvalidInput("OPENAI_API_KEY is not set".to_string()))?;
+        let end_point = std::env::var("OPENAI_BASE_URL")
+            .unwrap_or_else(|_| "https://api.openai.com/v1/".to_string());
+        Ok(Self::new(&end_point, Some(&api_key)))
     }
 
-    /// Switch the enable/disable state of a tool.
+    /// Set a custom header sent with every request.
+    ///
+    /// If a header with the same name is already set, it will be overwritten.
+    /// Headers with an invalid name or value are skipped when sending.
     ///
     /// # Arguments
     ///
-    /// * `tool_name` - The name of the tool.
-    /// * `t_enable` - True to enable, false to disable.
-    pub fn switch_tool(&mut self, tool_name: &str, t_enable: bool) {
-        if let Some((_, enable)) = self.tools.get_mut(tool_name) {
-            *enable = t_enable;
-        }
+    /// * `key` - The header name.
+    /// * `value` - The header value.
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        self.headers.insert(key.to_string(), value.to_string());
     }
 
-    /// Export the definitions of all enabled tools.
+    /// Set the organization used for requests (`OpenAI-Organization` header).
     ///
-    /// # Returns
+    /// # Arguments
     ///
-    /// A vector of function definitions.
-    pub fn export_tool_def(&self) -> Result<Vec<ToolDef>, ClientError> {
-        let mut defs = Vec::new();
-        for (tool_name, (tool, enable)) in self.tools.iter() {
-            if *enable {
-                defs.push(ToolDef {
-                    tool_type: "function".to_string(),
-                    function: FunctionDef {
-                        name: tool_name.clone(),
-                        description: tool.def_description().to_string(),
-                        parameters: tool.def_parameters(),
-                        strict: self.model_config.as_ref().ok_or(ClientError::ModelConfigNotSet)?.strict.unwrap_or(false),
-                    },
-                });
-            }
-        }
-        Ok(defs)
+    /// * `organization` - The organization ID.
+    pub fn with_organization(mut self, organization: &str) -> Self {
+        self.organization = Some(organization.to_string());
+        self
     }
 
-    /// Send a chat request to the API.
+    /// Set the project used for requests (`OpenAI-Project` header).
     ///
     /// # Arguments
     ///
-    /// * `model` - The model configuration.
-    /// * `prompt` - A vector of user and system messages.
+    /// * `project` - The project ID.
+    pub fn with_project(mut self, project: &str) -> Self {
+        self.project = Some(project.to_string());
+        self
+    }
+
+    /// Set the total timeout of a request.
+    ///
+    /// Requests exceeding the timeout fail with `ClientError::Timeout`.
+    /// This rebuilds the underlying HTTP client.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The total timeout, including reading the response body.
     ///
     /// # Returns
     ///
-    /// The API result or a ClientError.
-    pub async fn send(
-        &self,
-        prompt: &VecDeque<Message>,
-        model: Option<&ModelConfig>,
-    ) -> Result<APIResult, ClientError> {
-        match self
-            .call_api(
-                prompt,
-                Some(&serde_json::json!("none")),
-                model,
-            )
-            .await
+    /// The client, or a ClientError if the HTTP client cannot be built.
+    pub fn with_timeout(mut self, timeout: Duration) -> Result<Self, ClientError> {
+        self.timeout = Some(timeout);
+        self.client = self.build_http_client()?;
+        Ok(self)
+    }
+
+    /// Remove the total timeout of a request.
+    ///
+    /// Useful for long streamed responses.
+    /// This rebuilds the underlying HTTP client.
+    ///
+    /// # Returns
+    ///
+    /// The client, or a ClientError if the HTTP client cannot be built.
+    pub fn without_timeout(mut self) -> Result<Self, ClientError> {
+        self.timeout = None;
+        self.client = self.build_http_client()?;
+        Ok(self)
+    }
+
+    /// Set the timeout for establishing a connection.
+    ///
+    /// This rebuilds the underlying HTTP client.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The connect timeout.
+    ///
+    /// # Returns
+    ///
+    /// The client, or a ClientError if the HTTP client cannot be built.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Result<Self, ClientError> {
+        self.connect_timeout = Some(timeout);
+        self.client = self.build_http_client()?;
+        Ok(self)
+    }
+
+    /// Accept invalid TLS certificates, e.g. the self-signed certificate of a local or staging gateway.
+    ///
+    /// # Warning
+    ///
+    /// This disables certificate validation entirely: any certificate is trusted, including
+    /// expired ones and ones for other hostnames, so the connection (and the API key sent over it)
+    /// is open to man-in-the-middle attacks. Only use it against endpoints you control,
+    /// never in production. Disabled by default.
+    ///
+    /// This rebuilds the underlying HTTP client.
+    ///
+    /// # Arguments
+    ///
+    /// * `accept` - True to accept invalid certificates.
+    ///
+    /// # Returns
+    ///
+    /// The client, or a ClientError if the HTTP client cannot be built.
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Result<Self, ClientError> {
+        self.accept_invalid_certs = accept;
+        self.client = self.build_http_client()?;
+        Ok(self)
+    }
+
+    /// The total timeout of a request set with `with_timeout`.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// The connect timeout set with `with_connect_timeout`.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    /// Build the HTTP client from the current settings.
+    fn build_http_client(&self) -> Result<Client, ClientError> {
+        build_http_client(self.timeout, self.connect_timeout, self.accept_invalid_certs)
+    }
+
+    /// Set the default model configuration.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `model_config` - The model configuration.
+    pub fn set_model_config(&mut self, model_config: &ModelConfig) {
+        self.model_config = Some(model_config.clone());
+    }
+
+    /// Set the retry policy for rate limited or failed requests.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry_config` - The retry policy.
+    pub fn set_retry_config(&mut self, retry_config: &RetryConfig) {
+        self.retry_config = Some(retry_config.clone());
+    }
+
+    /// Enable or disable validating tool arguments against the tool's parameter schema.
+    ///
+    /// When enabled, a tool call whose arguments do not match the schema is not run,
+    /// and the validation errors are returned to the model as the tool result.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - True to validate tool arguments.
+    pub fn set_validate_tool_args(&mut self, enable: bool) {
+        self.validate_tool_args = enable;
+    }
+
+    /// Enable or disable returning `ClientError::Truncated` when the output was cut off.
+    ///
+    /// When enabled, the `generate*` methods of `OpenAIClientState` fail with the partial
+    /// content if the finish reason is `length`, and the message is not added to the prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - True to treat truncated output as an error.
+    pub fn set_error_on_truncation(&mut self, enable: bool) {
+        self.error_on_truncation = enable;
+    }
+
+    /// Enable or disable sanitizing message names.
+    ///
+    /// Names must match `^[a-zA-Z0-9_-]+$`. By default a request with an invalid name fails with
+    /// `ClientError::InvalidInput`; when enabled, the invalid characters are replaced with `_`.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - True to sanitize invalid names.
+    pub fn set_sanitize_names(&mut self, enable: bool) {
+        self.sanitize_names = enable;
+    }
+
+    /// Enable or disable sending developer messages as system messages.
+    ///
+    /// Many models and OpenAI compatible servers reject the `developer` role.
+    /// When enabled, `Message::Developer` is sent with the `system` role; the prompt itself is unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - True to send developer messages as system messages.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::collections::VecDeque;
+    /// # use call_agent::chat::{client::{ModelConfig, OpenAIClient}, prompt::Message};
+    /// let mut client = OpenAIClient::new("https://api.openai.com/v1/", None);
+    /// client.set_downgrade_developer_to_system(true);
+    ///
+    /// let prompt = VecDeque::from(vec![Message::developer("Answer in Japanese.")]);
+    /// let body = client.build_request_json(&prompt, None, Some(&ModelConfig::new("gpt-4o-mini"))).unwrap();
+    /// assert_eq!(body["messages"][0]["role"], "system");
+    /// ```
+    pub fn set_downgrade_developer_to_system(&mut self, enable: bool) {
+        self.downgrade_developer_to_system = enable;
+    }
+
+    /// Set the path of the chat completions API, for gateways that mount it elsewhere.
+    ///
+    /// The path is relative to the endpoint; a leading `/` is ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path (e.g. "custom/chat"). default: "chat/completions"
+    pub fn set_completions_path(&mut self, path: &str) {
+        self.completions_path = path.trim_start_matches('/').to_string();
+    }
+
+    /// Enable or disable recording the request and response bodies in `tracing` events.
+    ///
+    /// Only has an effect with the `tracing` feature.
+    ///
+    /// # Arguments
+    ///
+    /// * `enable` - True to record the message content.
+    pub fn set_trace_content(&mut self, enable: bool) {
+        self.trace_content = enable;
+    }
+
+    /// Register a tool.
+    ///
+    /// If a tool with the same name already exists, it will be overwritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `tool` - Reference-counted tool implementing the Tool trait.
+    pub fn def_tool<T: Tool + Send + Sync + 'static>(&mut self, tool: Arc<T>) {
+        self.tools
+            .insert(Tool::def_name(tool.as_ref()).to_string(), (RegisteredTool { tool, blocking: true }, true));
+    }
+
+    /// Register an async tool.
+    ///
+    /// If a tool with the same name already exists, it will be overwritten.
+    ///
+    /// # Arguments
+    ///
+    /// * `tool` - Reference-counted tool implementing the AsyncTool trait.
+    pub fn def_async_tool<T: AsyncTool + Send + Sync + 'static>(&mut self, tool: Arc<T>) {
+        self.tools
+            .insert(tool.def_name().to_string(), (RegisteredTool { tool, blocking: false }, true));
+    }
+
+    /// Get a registered tool by name.
+    ///
+    /// # Arguments
+    ///
+    /// * `tool_name` - The name of the tool.
+    ///
+    /// # Returns
+    ///
+    /// The tool and whether it is enabled, or None if no tool has this name.
+    pub fn get_tool(&self, tool_name: &str) -> Option<(Arc<dyn AsyncTool + Send + Sync>, bool)> {
+        self.tools.get(tool_name).map(|(registered, enable)| (Arc::clone(&registered.tool), *enable))
+    }
+
+    /// List all registered tools.
+    ///
+    /// # Returns
+    ///
+    /// A list of tuples containing (tool name, tool description, enabled flag).
+    pub fn list_tools(&self) -> Vec<(String, String, bool)> {
+        let mut tools = Vec::new();
+        for (tool_name, (registered, enable)) in self.tools.iter() {
+            tools.push((
+                tool_name.to_string(),
+                registered.tool.def_description().to_string(),
+                *enable,
+            ));
+        }
+        tools
+    }
+
+    /// Switch the enable/disable state of a tool.
+    ///
+    /// # Arguments
+    ///
+    /// * `tool_name` - The name of the tool.
+    /// * `t_enable` - True to enable, false to disable.
+    pub fn switch_tool(&mut self, tool_name: &str, t_enable: bool) {
+        if let Some((_, enable)) = self.tools.get_mut(tool_name) {
+            *enable = t_enable;
+        }
+    }
+
+    /// Remove a registered tool.
+    ///
+    /// # Arguments
+    ///
+    /// * `tool_name` - The name of the tool.
+    ///
+    /// # Returns
+    ///
+    /// True if the tool was registered.
+    pub fn remove_tool(&mut self, tool_name: &str) -> bool {
+        self.tools.remove(tool_name).is_some()
+    }
+
+    /// Remove all registered tools.
+    pub fn clear_tools(&mut self) {
+        self.tools.clear();
+    }
+
+    /// Export the definitions of all enabled tools.
+    ///
+    /// `strict` follows the model configuration of the client.
+    ///
+    /// # Returns
+    ///
+    /// A vector of function definitions.
+    pub fn export_tool_def(&self) -> Result<Vec<ToolDef>, ClientError> {
+        Ok(self.tool_defs(self.model_config.as_ref().is_some_and(ModelConfig::tool_strict)))
+    }
+
+    /// The definitions of all enabled tools for a request sent with `model_config`.
+    pub(crate) fn tool_defs_for(&self, model_config: &ModelConfig) -> Vec<ToolDef> {
+        self.tool_defs(model_config.tool_strict())
+    }
+
+    fn tool_defs(&self, strict: bool) -> Vec<ToolDef> {
+        let mut defs = Vec::new();
+        for (tool_name, (registered, enable)) in self.tools.iter() {
+            if *enable {
+                defs.push(ToolDef {
+                    tool_type: "function".to_string(),
+                    function: FunctionDef {
+                        name: tool_name.clone(),
+                        description: registered.tool.def_description().to_string(),
+                        parameters: registered.tool.def_parameters(),
+                        strict,
+                    },
+                });
+            }
+        }
+        defs
+    }
+
+    /// Run the tool calls requested by the model concurrently.
+    ///
+    /// Tools registered with `def_tool` are run on the blocking thread pool, those registered with
+    /// `def_async_tool` on the current task.
+    /// All tools are looked up before any of them runs. The results are returned
+    /// in the same order as `calls`, so each result keeps its `tool_call_id`.
+    /// A failing or panicking tool does not affect the others; its error is returned as the tool result.
+    ///
+    /// # Arguments
+    ///
+    /// * `calls` - The tool calls requested by the model.
+    /// * `show_call` - Optional callback function to show the tool call.(eg, `show_call("tool_name", "args")`)
+    ///
+    /// # Returns
+    ///
+    /// A vector of tool messages or a ClientError.
+    pub async fn run_tool_calls<F>(&self, calls: &[FunctionCall], show_call: Option<&F>) -> Result<Vec<Message>, ClientError>
+    where F: Fn(&str, &serde_json::Value) {
+        self.check_tool_calls(calls)?;
+        let mut tasks = Vec::with_capacity(calls.len());
+        for call in calls {
+            let tool = self.find_tool(&call.function.name)?;
+            if let Some(show_call) = show_call {
+                show_call(&call.function.name, &call.function.arguments);
+            }
+            let invalid = if self.validate_tool_args {
+                Self::validate_tool_args(tool.tool.as_ref(), &call.function.arguments)
+            } else {
+                None
+            };
+            let tool = tool.clone();
+            tasks.push(async move {
+                let result = match invalid {
+                    Some(errors) => Err(format!("invalid arguments: {}", errors)),
+                    None => Self::run_tool(tool, call.function.arguments.clone()).await,
+                };
+                Message::Tool {
+                    tool_call_id: call.id.clone(),
+                    content: result.unwrap_or_else(|e| vec![MessageContext::Text(format!("Error: {}", e))]),
+                    created_at: None,
+                    metadata: None,
+                }
+            });
+        }
+        Ok(join_all(tasks).await)
+    }
+
+    /// Check that every tool requested by the model is registered and enabled.
+    ///
+    /// # Returns
+    ///
+    /// `ClientError::ToolNotFound` or `ClientError::ToolDisabled` for the first tool that cannot be run.
+    pub(crate) fn check_tool_calls(&self, calls: &[FunctionCall]) -> Result<(), ClientError> {
+        calls.iter().try_for_each(|call| self.find_tool(&call.function.name).map(|_| ()))
+    }
+
+    /// Look up an enabled tool by name.
+    fn find_tool(&self, name: &str) -> Result<&RegisteredTool, ClientError> {
+        match self.tools.get(name) {
+            Some((tool, true)) => Ok(tool),
+            Some((_, false)) => Err(ClientError::ToolDisabled(name.to_string())),
+            None => Err(ClientError::ToolNotFound(name.to_string())),
+        }
+    }
+
+    /// Run a single tool.
+    ///
+    /// Synchronous tools are run on the blocking thread pool, so several of them run in parallel
+    /// without stalling the executor. A panicking tool is reported like a failed one so the
+    /// other calls still complete.
+    async fn run_tool(registered: RegisteredTool, args: serde_json::Value) -> Result<Vec<MessageContext>, String> {
+        let tool = registered.tool;
+        if registered.blocking {
+            // The future of a synchronous tool completes on its first poll.
+            tokio::task::spawn_blocking(move || futures::executor::block_on(tool.run_rich(args)))
+                .await
+                .unwrap_or_else(|_| Err("tool panicked".to_string()))
+        } else {
+            AssertUnwindSafe(tool.run_rich(args))
+                .catch_unwind()
+                .await
+                .unwrap_or_else(|_| Err("tool panicked".to_string()))
+        }
+    }
+
+    /// Check the arguments of a tool call against the parameter schema of the tool.
+    ///
+    /// # Returns
+    ///
+    /// The validation errors joined into one message, or None if the arguments are valid.
+    fn validate_tool_args(tool: &(dyn AsyncTool + Send + Sync), args: &serde_json::Value) -> Option<String> {
+        let validator = match jsonschema::validator_for(&tool.def_parameters()) {
+            Ok(validator) => validator,
+            Err(e) => {
+                // The schema is the tool's own fault, so the call is not blocked.
+                log::warn!("Invalid parameter schema of tool {}: {}", tool.def_name(), e);
+                return None;
+            }
+        };
+        let errors: Vec<String> = validator.iter_errors(args).map(|e| e.to_string()).collect();
+        if errors.is_empty() {
+            None
+        } else {
+            Some(errors.join("; "))
+        }
+    }
+
+    /// Send a chat request to the API.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model configuration.
+    /// * `prompt` - A vector of user and system messages.
+    ///
+    /// # Returns
+    ///
+    /// The API result or a ClientError.
+    pub async fn send(
+        &self,
+        prompt: &VecDeque<Message>,
+        model: Option<&ModelConfig>,
+    ) -> Result<APIResult, ClientError> {
+        match self
+            .call_api(
+                prompt,
+                Some(&ToolChoice::None),
+                model,
+            )
+            .await
         {
             Ok(res) => Ok(res),
             Err(e) => Err(e),
@@ -204,7 +1222,7 @@ impl OpenAIClient {
         match self
             .call_api(
                 prompt,
-                Some(&serde_json::json!("auto")),
+                Some(&ToolChoice::Auto),
                 model,
             )
             .await
@@ -232,7 +1250,7 @@ impl OpenAIClient {
         match self
             .call_api(
                 prompt,
-                Some(&serde_json::json!("required")),
+                Some(&ToolChoice::Required),
                 model,
             )
             .await
@@ -259,7 +1277,7 @@ impl OpenAIClient {
         tool_name: &str,
         model: Option<&ModelConfig>,
     ) -> Result<APIResult, ClientError> {
-        let function_call = serde_json::json!({"type": "function", "function": {"name": tool_name}});
+        let function_call = ToolChoice::Function(tool_name.to_string());
 
         match self
             .call_api(
@@ -274,16 +1292,112 @@ impl OpenAIClient {
         }
     }
 
+    /// Create embeddings for the given texts.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The embedding model; e.g. "text-embedding-3-small".
+    /// * `input` - The texts to embed. A single text can be passed as a one-element vector.
+    ///
+    /// # Returns
+    ///
+    /// One embedding per input, in the same order, or a ClientError.
+    pub async fn embeddings(&self, model: &str, input: Vec<String>) -> Result<Vec<Vec<f32>>, ClientError> {
+        let request = EmbeddingRequest {
+            model: model.to_string(),
+            input,
+        };
+        let mut response: EmbeddingResponse = self.post_json("embeddings", &request).await?;
+        response.data.sort_by_key(|data| data.index);
+        Ok(response.data.into_iter().map(|data| data.embedding).collect())
+    }
+
+    /// Classify whether the input violates the usage policies.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The text to classify.
+    ///
+    /// # Returns
+    ///
+    /// The moderation result or a ClientError.
+    pub async fn moderate(&self, input: &str) -> Result<ModerationResult, ClientError> {
+        let request = ModerationRequest {
+            input: input.to_string(),
+        };
+        let response: ModerationResponse = self.post_json("moderations", &request).await?;
+        response.results.into_iter().next().ok_or(ClientError::InvalidResponse)
+    }
+
+    /// Generate images from a text prompt.
+    ///
+    /// Base64 images can be passed back to a vision prompt with `MessageImage::from_base64(&data, "image/png")`.
+    /// A prompt rejected by the content policy is returned as `ClientError::ApiError`.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The image model; e.g. "dall-e-3". `None` uses the API default (dall-e-2).
+    /// * `prompt` - Description of the image to generate.
+    /// * `size` - Size of the image; e.g. "1024x1024".
+    /// * `n` - Number of images to generate.
+    /// * `response_format` - Whether to return URLs or base64-encoded images.
+    ///
+    /// # Returns
+    ///
+    /// The URLs or base64 data of the images, or a ClientError.
+    pub async fn generate_image(&self, model: Option<&str>, prompt: &str, size: &str, n: u32, response_format: ImageResponseFormat) -> Result<Vec<String>, ClientError> {
+        let request = ImageGenerationRequest {
+            model: model.map(|model| model.to_string()),
+            prompt: prompt.to_string(),
+            size: size.to_string(),
+            n,
+            response_format,
+        };
+        let response: ImageGenerationResponse = self.post_json("images/generations", &request).await?;
+        response
+            .data
+            .into_iter()
+            .map(|image| match response_format {
+                ImageResponseFormat::Url => image.url,
+                ImageResponseFormat::B64Json => image.b64_json,
+            })
+            .collect::<Option<Vec<String>>>()
+            .ok_or(ClientError::InvalidResponse)
+    }
+
+    /// Transcribe audio into text.
+    ///
+    /// # Arguments
+    ///
+    /// * `audio_bytes` - The audio file content.
+    /// * `filename` - The file name; its extension tells the API the audio format (e.g. "speech.mp3").
+    /// * `model` - The transcription model; e.g. "whisper-1".
+    ///
+    /// # Returns
+    ///
+    /// The transcribed text or a ClientError.
+    pub async fn transcribe(&self, audio_bytes: Vec<u8>, filename: &str, model: &str) -> Result<String, ClientError> {
+        if !self.end_point.starts_with("https://") && !self.end_point.starts_with("http://") {
+            return Err(ClientError::InvalidEndpoint);
+        }
+        // The form is rebuilt for each attempt since it cannot be reused once sent.
+        let res = self
+            .post_request(&self.end_point, self.api_key.as_deref(), "audio/transcriptions", |builder| {
+                let file = Part::bytes(audio_bytes.clone()).file_name(filename.to_string());
+                builder.multipart(Form::new().text("model", model.to_string()).part("file", file))
+            })
+            .await?;
+        let response: TranscriptionResponse = Self::parse_response(res).await?;
+        Ok(response.text)
+    }
+
     /// Calls the OpenAI chat completions API.
     ///
     /// # Arguments
     ///
     /// * `model` - The model name; e.g. "GPT-4o".
     /// * `prompt` - The list of messages.
-    /// * `function_call` - Indicates function call mode:
-    ///   - "auto"
-    ///   - "none"
-    ///   - { "name": "get_weather" }
+    /// * `tool_choice` - Indicates function call mode (see `ToolChoice`); `None` leaves it to the API default.
     /// * `temp` - Temperature parameter.
     /// * `max_token` - Maximum tokens parameter.
     /// * `top_p` - Top-p sampling parameter.
@@ -291,91 +1405,324 @@ impl OpenAIClient {
     /// # Returns
     ///
     /// An APIResult on success or a ClientError on failure.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "call_api", skip_all))]
     pub async fn call_api(
         &self,
         prompt: &VecDeque<Message>,
-        tool_choice: Option<&serde_json::Value>,
+        tool_choice: Option<&ToolChoice>,
+        model_config: Option<&ModelConfig>,
+    ) -> Result<APIResult, ClientError> {
+        let model_config = model_config.or(self.model_config.as_ref()).ok_or(ClientError::ModelConfigNotSet)?;
+        let tools = self.tool_defs_for(model_config);
+        self.complete(prompt, &tools, model_config, tool_choice).await
+    }
+
+    /// Count the tokens the messages use in the prompt, without sending a request.
+    ///
+    /// Includes the per-message overhead of the chat format. Images are approximated by their detail level.
+    /// With the `tokens` feature the text is counted with the BPE of `model`, otherwise it is a heuristic.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The list of messages.
+    /// * `model` - The model name; e.g. "gpt-4o".
+    pub fn count_tokens(&self, messages: &VecDeque<Message>, model: &str) -> usize {
+        tokenizer::count_prompt_tokens(messages, model)
+    }
+
+    /// Build the chat completions request body without sending it.
+    ///
+    /// Useful to inspect exactly what `call_api` would serialize.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The list of messages.
+    /// * `tool_choice` - Indicates function call mode (see `call_api`).
+    /// * `model_config` - The model configuration.
+    ///
+    /// # Returns
+    ///
+    /// The request body as JSON or a ClientError.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::collections::VecDeque;
+    /// # use call_agent::chat::{api::ToolChoice, client::{ModelConfig, OpenAIClient}, prompt::Message};
+    /// let client = OpenAIClient::new("https://api.openai.com/v1/", None);
+    /// let config = ModelConfig::builder().model("gpt-4o-mini").temperature(0.5).build();
+    /// let prompt = VecDeque::from(vec![Message::user("Hello")]);
+    ///
+    /// let body = client.build_request_json(&prompt, Some(&ToolChoice::Auto), Some(&config)).unwrap();
+    /// assert_eq!(body, serde_json::json!({
+    ///     "model": "gpt-4o-mini",
+    ///     "messages": [{"role": "user", "content": "Hello"}],
+    ///     "temperature": 0.5,
+    /// }));
+    /// ```
+    pub fn build_request_json(
+        &self,
+        prompt: &VecDeque<Message>,
+        tool_choice: Option<&ToolChoice>,
         model_config: Option<&ModelConfig>,
+    ) -> Result<serde_json::Value, ClientError> {
+        let model_config = model_config.or(self.model_config.as_ref()).ok_or(ClientError::ModelConfigNotSet)?;
+        let tools = self.tool_defs_for(model_config);
+        let request = self.build_request(model_config, prompt, &tools, tool_choice)?;
+        serde_json::to_value(&request).map_err(|e| ClientError::InvalidInput(e.to_string()))
+    }
+
+    /// Send the chat completions request and parse the response.
+    async fn complete_chat(
+        &self,
+        prompt: &VecDeque<Message>,
+        tools: &[ToolDef],
+        model_config: &ModelConfig,
+        tool_choice: Option<&ToolChoice>,
     ) -> Result<APIResult, ClientError> {
-        let url = format!("{}/chat/completions", self.end_point);
+        let url = format!("{}/{}", self.end_point, self.completions_path);
         if !url.starts_with("https://") && !url.starts_with("http://") {
             return Err(ClientError::InvalidEndpoint);
         }
 
-        let model_config = model_config.unwrap_or(self.model_config.as_ref().ok_or(ClientError::ModelConfigNotSet)?);
-        let tools = self.export_tool_def()?;
-        let res = self.request_api(&self.end_point, self.api_key.as_deref(), model_config, prompt, &tools, tool_choice.unwrap_or(&serde_json::Value::Null)).await?;
-
-        let headers = APIResponseHeaders {
-            retry_after: res
-                .headers()
-                .get("Retry-After")
-                .and_then(|v| v.to_str().ok().and_then(|v| v.parse().ok())),
-            reset: res
-                .headers()
-                .get("X-RateLimit-Reset")
-                .and_then(|v| v.to_str().ok().and_then(|v| v.parse().ok())),
-            rate_limit: res
-                .headers()
-                .get("X-RateLimit-Remaining")
-                .and_then(|v| v.to_str().ok().and_then(|v| v.parse().ok())),
-            limit: res
-                .headers()
-                .get("X-RateLimit-Limit")
-                .and_then(|v| v.to_str().ok().and_then(|v| v.parse().ok())),
-            extra_other: res
-                .headers()
-                .iter()
-                .map(|(k, v)| {
-                    (
-                        k.as_str().to_string(),
-                        v.to_str().unwrap_or("").to_string(),
-                    )
-                })
-                .collect(),
-        };
-        let text = res.text().await.map_err(|_| ClientError::InvalidResponse)?;
+        let res = self.request_api(&self.end_point, self.api_key.as_deref(), model_config, prompt, tools, tool_choice).await?;
+
+        let headers = APIResponseHeaders::from_headers(res.headers());
+        let text = res.text().await.map_err(|e| {
+            if e.is_timeout() { ClientError::Timeout } else { ClientError::InvalidResponse }
+        })?;
         log::debug!("Response: {}", text);
+        #[cfg(feature = "tracing")]
+        if self.trace_content {
+            tracing::debug!(response = %text, "chat completion response");
+        }
         let response_body: APIResponse =
             serde_json::from_str(&text).map_err(|_| {
             ClientError::InvalidResponse
             })?;
 
+        // Surface the error reported by the API instead of a generic failure.
+        if let Some(err) = response_body.error {
+            return Err(err.into());
+        }
+
         Ok(APIResult {
             response: response_body,
             headers,
         })
     }
 
-    pub async fn request_api(&self ,end_point: &str, api_key: Option<&str>, model_config: &ModelConfig ,message: &VecDeque<Message>, tools: &Vec<ToolDef>, tool_choice: &serde_json::Value) -> Result<Response, ClientError> {
-        let request = APIRequest {
+    /// Calls the OpenAI chat completions API in streaming mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The list of messages.
+    /// * `tool_choice` - Indicates function call mode (see `call_api`).
+    /// * `model_config` - The model configuration.
+    ///
+    /// # Returns
+    ///
+    /// The raw streaming response on success or a ClientError on failure.
+    pub async fn call_api_stream(
+        &self,
+        prompt: &VecDeque<Message>,
+        tool_choice: Option<&ToolChoice>,
+        model_config: Option<&ModelConfig>,
+    ) -> Result<Response, ClientError> {
+        let url = format!("{}/{}", self.end_point, self.completions_path);
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(ClientError::InvalidEndpoint);
+        }
+
+        let model_config = model_config.or(self.model_config.as_ref()).ok_or(ClientError::ModelConfigNotSet)?;
+        let tools = self.tool_defs_for(model_config);
+        let mut request = self.build_request(model_config, prompt, &tools, tool_choice)?;
+        request.stream = Some(true);
+        let res = self.send_chat_request(&self.end_point, self.api_key.as_deref(), &request).await?;
+
+        if !res.status().is_success() {
+            let text = res.text().await.map_err(|_| ClientError::InvalidResponse)?;
+            log::debug!("Response: {}", text);
+            return match serde_json::from_str::<APIResponse>(&text).ok().and_then(|body| body.error) {
+                Some(err) => Err(err.into()),
+                None => Err(ClientError::InvalidResponse),
+            };
+        }
+        Ok(res)
+    }
+
+    pub async fn request_api(&self ,end_point: &str, api_key: Option<&str>, model_config: &ModelConfig ,message: &VecDeque<Message>, tools: &[ToolDef], tool_choice: Option<&ToolChoice>) -> Result<Response, ClientError> {
+        let request = self.build_request(model_config, message, tools, tool_choice)?;
+        self.send_chat_request(end_point, api_key, &request).await
+    }
+
+    /// Post a chat completions request.
+    ///
+    /// With the `tracing` feature, the request is wrapped in a `chat_completion` span recording
+    /// the model, the number of messages and tools, the response status, the latency and the
+    /// `x-request-id` response header.
+    async fn send_chat_request(&self, end_point: &str, api_key: Option<&str>, request: &APIRequest) -> Result<Response, ClientError> {
+        #[cfg(feature = "tracing")]
+        {
+            use tracing::Instrument;
+
+            let span = tracing::info_span!(
+                "chat_completion",
+                model = %request.model,
+                messages = request.messages.len(),
+                tools = request.tools.len(),
+                stream = request.stream.unwrap_or(false),
+                status = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+                request_id = tracing::field::Empty,
+            );
+            if self.trace_content {
+                let body = serde_json::to_string(request).unwrap_or_default();
+                span.in_scope(|| tracing::debug!(request = %body, "chat completion request"));
+            }
+
+            let start = std::time::Instant::now();
+            let res = self
+                .post_request(end_point, api_key, &self.completions_path, |builder| builder.json(request))
+                .instrument(span.clone())
+                .await;
+            span.record("latency_ms", start.elapsed().as_millis() as u64);
+            match &res {
+                Ok(res) => {
+                    span.record("status", res.status().as_u16());
+                    if let Some(request_id) = res.headers().get("x-request-id").and_then(|value| value.to_str().ok()) {
+                        span.record("request_id", request_id);
+                    }
+                }
+                Err(e) => span.in_scope(|| tracing::warn!(error = %e, "chat completion request failed")),
+            }
+            res
+        }
+        #[cfg(not(feature = "tracing"))]
+        self.post_request(end_point, api_key, &self.completions_path, |builder| builder.json(request)).await
+    }
+
+    fn build_request(&self, model_config: &ModelConfig, message: &VecDeque<Message>, tools: &[ToolDef], tool_choice: Option<&ToolChoice>) -> Result<APIRequest, ClientError> {
+        model_config.validate()?;
+        let mut messages = message.clone();
+        for message in messages.iter_mut() {
+            if self.sanitize_names {
+                message.sanitize_name();
+            } else {
+                message.validate()?;
+            }
+            if self.downgrade_developer_to_system {
+                if let Message::Developer { name, content, created_at, metadata } = message {
+                    *message = Message::System {
+                        name: name.take(),
+                        content: std::mem::take(content),
+                        created_at: created_at.take(),
+                        metadata: metadata.take(),
+                    };
+                }
+            }
+        }
+        // The tools may have been exported with another configuration.
+        let strict = model_config.tool_strict();
+        if model_config.strict == Some(true) && !strict && !tools.is_empty() {
+            log::warn!("strict is ignored while parallel_tool_calls is enabled; set parallel_tool_calls to false to use it");
+        }
+        let tools = tools
+            .iter()
+            .cloned()
+            .map(|mut tool| {
+                tool.function.strict = strict;
+                tool
+            })
+            .collect();
+        Ok(APIRequest {
             model:                  model_config.model.clone(),
-            messages:               message.clone(),
-            tools:                  tools.clone(),
-            tool_choice:            tool_choice.clone(),
+            messages,
+            tools,
+            tool_choice:            tool_choice.cloned(),
             parallel_tool_calls:    model_config.parallel_tool_calls,
             temperature:            model_config.temperature,
             max_completion_tokens:  model_config.max_completion_tokens,
+            token_param:            model_config.token_param.unwrap_or_default(),
             top_p:                  model_config.top_p,
             reasoning_effort:       model_config.reasoning_effort.clone(),
             presence_penalty:       model_config.presence_penalty,
+            frequency_penalty:      model_config.frequency_penalty,
+            stop:                   model_config.stop.clone(),
+            logit_bias:             model_config.logit_bias.clone(),
+            n:                      model_config.n,
+            user:                   model_config.user.clone(),
+            logprobs:               model_config.logprobs,
+            top_logprobs:           model_config.top_logprobs,
             web_search_options:     model_config.web_search_options.clone(),
-        };
+            response_format:        model_config.response_format.clone(),
+            stream:                 None,
+            stream_options:         model_config.stream_options.clone(),
+        })
+    }
+
+    /// Send a POST request to `{end_point}/{path}`, retrying according to the retry policy.
+    ///
+    /// `body` attaches the request body; it is called again for each attempt.
+    async fn post_request<F>(&self, end_point: &str, api_key: Option<&str>, path: &str, body: F) -> Result<Response, ClientError>
+    where F: Fn(RequestBuilder) -> RequestBuilder {
+        send_with_retry(self.retry_config.as_ref(), || {
+            let mut builder = self
+                .client
+                .post(format!("{}/{}", end_point, path));
+            if let Some(api_version) = &self.azure_api_version {
+                builder = builder.query(&[("api-version", api_version)]);
+            }
+            // Local servers may reject an empty token, so no authorization is sent without a key.
+            if let Some(api_key) = api_key {
+                builder = match self.azure_api_version {
+                    Some(_) => builder.header("api-key", api_key),
+                    None => builder.header("authorization", format!("Bearer {}", api_key)),
+                };
+            }
+            if let Some(organization) = &self.organization {
+                builder = builder.header("OpenAI-Organization", organization);
+            }
+            if let Some(project) = &self.project {
+                builder = builder.header("OpenAI-Project", project);
+            }
+            body(builder.headers(custom_headers(&self.headers)))
+        })
+        .await
+    }
 
+    /// Send a JSON request to an endpoint other than chat completions and parse the response.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path relative to the endpoint (e.g. "embeddings").
+    /// * `body` - The request body.
+    async fn post_json<B, T>(&self, path: &str, body: &B) -> Result<T, ClientError>
+    where
+        B: Serialize,
+        T: DeserializeOwned,
+    {
+        if !self.end_point.starts_with("https://") && !self.end_point.starts_with("http://") {
+            return Err(ClientError::InvalidEndpoint);
+        }
         let res = self
-            .client
-            .post(&format!("{}/chat/completions", end_point))
-            .header("Content-Type", "application/json")
-            .header(
-                "authorization",
-                format!("Bearer {}", api_key.as_deref().unwrap_or("")),
-            )
-            .json(&request)
-            .send()
-            .await
-            .map_err(|_| ClientError::NetworkError)?;
+            .post_request(&self.end_point, self.api_key.as_deref(), path, |builder| builder.json(body))
+            .await?;
+        Self::parse_response(res).await
+    }
 
-        Ok(res)
+    /// Parse a JSON response, surfacing the error reported by the API.
+    async fn parse_response<T: DeserializeOwned>(res: Response) -> Result<T, ClientError> {
+        let status = res.status();
+        let text = res.text().await.map_err(ClientError::from_reqwest)?;
+        log::debug!("Response: {}", text);
+        if let Ok(APIErrorResponse { error: Some(err) }) = serde_json::from_str(&text) {
+            return Err(err.into());
+        }
+        if !status.is_success() {
+            return Err(ClientError::InvalidResponse);
+        }
+        serde_json::from_str(&text).map_err(|e| ClientError::Deserialize(e.to_string()))
     }
 
     /// Create a new prompt conversation.
@@ -387,19 +1734,132 @@ impl OpenAIClient {
         OpenAIClientState {
             prompt: VecDeque::new(),
             client: self.clone(),
+            backend: None,
             entry_limit: None,
+            usage_total: APIUsage::default(),
+            system_prompt: None,
         }
     }
+
+    /// Create a new prompt conversation that sends its requests through another backend.
+    ///
+    /// The tools and the default model configuration of this client are still used,
+    /// but its endpoint and API key are not. Streaming is not available on the returned state
+    /// (see the limitations of `ChatBackend`).
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The backend to send the requests with.
+    ///
+    /// # Returns
+    ///
+    /// A new OpenAIClientState with an empty message history.
+    pub fn create_prompt_with_backend<B: ChatBackend>(&self, backend: B) -> OpenAIClientState<B> {
+        OpenAIClientState {
+            prompt: VecDeque::new(),
+            client: self.clone(),
+            backend: Some(backend),
+            entry_limit: None,
+            usage_total: APIUsage::default(),
+            system_prompt: None,
+        }
+    }
+
+    /// Create a prompt conversation from a history saved with `OpenAIClientState::export_history`.
+    ///
+    /// # Arguments
+    ///
+    /// * `json` - A JSON array of messages.
+    ///
+    /// # Returns
+    ///
+    /// A new OpenAIClientState with the restored message history.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use call_agent::chat::{client::OpenAIClient, function::{FunctionCall, FunctionCallInner}, prompt::{Message, MessageContext, MessageImage}};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() -> Result<(), call_agent::chat::err::ClientError> {
+    /// let client = OpenAIClient::new("https://api.openai.com/v1/", None);
+    /// let mut state = client.create_prompt();
+    /// state.add(vec![
+    ///     Message::User {
+    ///         name: None,
+    ///         content: vec![
+    ///             MessageContext::Text("What is in this image?".to_string()),
+    ///             MessageContext::Image(MessageImage::from_base64("iVBORw0KGgo=", "image/png")),
+    ///         ],
+    ///         created_at: None,
+    ///         metadata: Some(serde_json::json!({ "source": "web" })),
+    ///     },
+    ///     Message::Assistant {
+    ///         name: None,
+    ///         content: vec![],
+    ///         tool_calls: Some(vec![FunctionCall {
+    ///             id: "call_1".to_string(),
+    ///             tool_type: "function".to_string(),
+    ///             function: FunctionCallInner {
+    ///                 name: "describe".to_string(),
+    ///                 arguments: serde_json::json!({ "detail": "high" }),
+    ///             },
+    ///         }]),
+    ///         created_at: None,
+    ///         metadata: None,
+    ///     },
+    ///     Message::Tool {
+    ///         tool_call_id: "call_1".to_string(),
+    ///         content: vec![MessageContext::Text("a cat".to_string())],
+    ///         created_at: None,
+    ///         metadata: None,
+    ///     },
+    /// ]).await;
+    ///
+    /// let json = state.export_history()?;
+    /// let restored = client.restore_prompt(&json)?;
+    /// assert_eq!(restored.export_history()?, json);
+    /// assert_eq!(restored.prompt[0].metadata(), Some(&serde_json::json!({ "source": "web" })));
+    /// assert!(restored.prompt[0].created_at().is_some());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn restore_prompt(&self, json: &str) -> Result<OpenAIClientState, ClientError> {
+        let mut state = self.create_prompt();
+        state.import_history(json)?;
+        Ok(state)
+    }
+}
+
+#[async_trait]
+impl ChatBackend for OpenAIClient {
+    async fn complete(
+        &self,
+        prompt: &VecDeque<Message>,
+        tools: &[ToolDef],
+        config: &ModelConfig,
+        tool_choice: Option<&ToolChoice>,
+    ) -> Result<APIResult, ClientError> {
+        self.complete_chat(prompt, tools, config, tool_choice).await
+    }
 }
 
 /// Represents a client state with a prompt history.
+///
+/// Requests are sent through `backend` if it is set, otherwise through `client`.
 #[derive(Clone)]
-pub struct OpenAIClientState {
+pub struct OpenAIClientState<B: ChatBackend = OpenAIClient> {
     /// Conversation history messages.
     pub prompt: VecDeque<Message>,
     /// Reference to the OpenAIClient.
+    /// Holds the tools and the default model configuration.
     pub client: OpenAIClient,
+    /// Backend the requests are sent with, instead of `client`.
+    pub backend: Option<B>,
     pub entry_limit: Option<u64>,
+    /// Token usage summed over every request sent by the `generate*` methods.
+    pub usage_total: APIUsage,
+    /// System prompt set with `set_system_prompt`, kept as the first message.
+    pub system_prompt: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -408,39 +1868,266 @@ pub struct GenerateResponse {
     pub has_tool_calls: bool,
     pub content: Option<String>,
     pub tool_calls: Option<Vec<FunctionCall>>,
+    /// The reason the model stopped generating (e.g. `Length` when truncated).
+    pub finish_reason: FinishReason,
+    /// Annotations of the reply, such as the URL citations of a web search.
+    pub annotations: Option<Vec<Annotation>>,
     pub api_result: APIResult,
 }
 
-impl<'a> OpenAIClientState {
-    /// Add messages to the conversation prompt.
+impl GenerateResponse {
+    /// Build a response from the first choice of an API result.
+    fn from_result(api_result: APIResult, error_on_truncation: bool) -> Result<Self, ClientError> {
+        let choice = api_result.first_choice()?;
+        if let Some(refusal) = &choice.message.refusal {
+            return Err(ClientError::Refusal(refusal.clone()));
+        }
+        if error_on_truncation && choice.finish_reason == FinishReason::Length {
+            return Err(ClientError::Truncated(choice.message.content.clone().unwrap_or_default()));
+        }
+        let content = choice.message.content.clone();
+        let tool_calls = choice.message.tool_calls.clone();
+        let finish_reason = choice.finish_reason.clone();
+        let annotations = choice.message.annotations.clone();
+
+        Ok(Self {
+            has_content: content.is_some(),
+            has_tool_calls: tool_calls.is_some(),
+            content,
+            tool_calls,
+            finish_reason,
+            annotations,
+            api_result,
+        })
+    }
+}
+
+impl<B: ChatBackend> OpenAIClientState<B> {
+    /// Add messages to the conversation prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - A vector of messages to add.
+    ///
+    /// # Returns
+    ///
+    /// A mutable reference to self.
+    pub async fn add(&mut self, messages: Vec<Message>) -> &mut Self {
+        self.push(messages);
+        self
+    }
+
+    /// Append messages to the prompt while respecting the entry limit.
+    pub(crate) fn push(&mut self, mut messages: Vec<Message>) {
+        self.make_room(messages.len());
+        messages.iter_mut().for_each(Message::stamp);
+        self.prompt.extend(messages);
+    }
+
+    /// Drop the oldest messages until `incoming` more fit in the entry limit.
+    ///
+    /// The system prompt set with `set_system_prompt` is never dropped.
+    fn make_room(&mut self, incoming: usize) {
+        let Some(limit) = self.entry_limit else {
+            return;
+        };
+        let keep = usize::from(self.system_prompt.is_some() && matches!(self.prompt.front(), Some(Message::System { .. })));
+        while self.prompt.len() as u64 + incoming as u64 > limit && self.prompt.len() > keep {
+            self.prompt.remove(keep);
+        }
+    }
+
+    /// Set the system prompt, kept as the first message of the conversation.
+    ///
+    /// If the prompt already has a system message, its content is replaced (and it is moved
+    /// to the front) instead of adding another one. The system prompt is not dropped by the
+    /// entry limit and is kept by `clear`.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The system prompt.
+    pub fn set_system_prompt(&mut self, text: &str) {
+        let existing = self
+            .prompt
+            .iter()
+            .position(|message| matches!(message, Message::System { .. }))
+            .and_then(|pos| self.prompt.remove(pos));
+        let message = match existing {
+            Some(Message::System { name, created_at, metadata, .. }) => Message::System {
+                name,
+                content: text.to_string(),
+                created_at,
+                metadata,
+            },
+            _ => {
+                let mut message = Message::system(text);
+                message.stamp();
+                message
+            }
+        };
+        self.prompt.push_front(message);
+        self.system_prompt = Some(text.to_string());
+    }
+
+    /// Add messages to the beginning of the conversation prompt.
+    ///
+    /// Each message is inserted before the previous one, so they end up in reverse order.
+    /// The system prompt set with `set_system_prompt` stays the first message.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - A vector of messages to add.
+    ///
+    /// # Returns
+    ///
+    /// A mutable reference to self.
+    pub async fn add_last(&mut self, messages: Vec<Message>) -> &mut Self {
+        self.make_room(messages.len());
+        let start = usize::from(self.system_prompt.is_some() && matches!(self.prompt.front(), Some(Message::System { .. })));
+        for mut msg in messages {
+            msg.stamp();
+            self.prompt.insert(start, msg);
+        }
+        self
+    }
+
+    /// Token usage summed over every request sent by the `generate*` methods of this state.
+    ///
+    /// `generate_n` does not modify the state, so its usage is not included.
+    pub fn total_usage(&self) -> &APIUsage {
+        &self.usage_total
+    }
+
+    /// Reset the usage total to zero, e.g. at the start of a new billing period.
+    pub fn reset_usage(&mut self) {
+        self.usage_total = APIUsage::default();
+    }
+
+    /// Send the prompt with the enabled tools through the backend.
+    async fn complete(&self, model: &ModelConfig, tool_choice: ToolChoice) -> Result<APIResult, ClientError> {
+        let tools = self.client.tool_defs_for(model);
+        match &self.backend {
+            Some(backend) => backend.complete(&self.prompt, &tools, model, Some(&tool_choice)).await,
+            None => self.client.complete(&self.prompt, &tools, model, Some(&tool_choice)).await,
+        }
+    }
+
+    /// Add the usage of an API result to the running total.
+    fn record_usage(&mut self, result: &APIResult) {
+        if let Some(usage) = &result.response.usage {
+            self.usage_total.add(usage);
+        }
+    }
+
+    /// Create an independent branch of this conversation.
+    ///
+    /// The branch gets its own copy of the message history, entry limit and usage total,
+    /// so messages added to or generated in the branch do not affect this state, and vice versa.
+    /// The client configuration and registered tools are shared (tools are reference counted).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use call_agent::chat::{client::OpenAIClient, prompt::Message};
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let client = OpenAIClient::new("https://api.openai.com/v1/", None);
+    /// let mut parent = client.create_prompt();
+    /// parent.add(vec![Message::user("Hello")]).await;
+    ///
+    /// let mut branch = parent.branch();
+    /// branch.add(vec![Message::user("Tell me a joke")]).await;
+    ///
+    /// assert_eq!(parent.prompt.len(), 1);
+    /// assert_eq!(branch.prompt.len(), 2);
+    /// # }
+    /// ```
+    pub fn branch(&self) -> Self
+    where
+        B: Clone,
+    {
+        self.clone()
+    }
+
+    /// Fork this conversation into an independent state.
+    ///
+    /// Same as `branch`.
+    pub fn fork(&self) -> Self
+    where
+        B: Clone,
+    {
+        self.branch()
+    }
+
+    /// Export the conversation history as a JSON array of messages.
+    ///
+    /// Unlike the request body, each message keeps its `created_at` and `metadata`.
+    ///
+    /// # Returns
+    ///
+    /// The JSON string, which can be restored with `import_history`.
+    pub fn export_history(&self) -> Result<String, ClientError> {
+        let messages = self
+            .prompt
+            .iter()
+            .map(Message::to_transcript)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| ClientError::InvalidInput(e.to_string()))?;
+        serde_json::to_string(&messages).map_err(|e| ClientError::InvalidInput(e.to_string()))
+    }
+
+    /// Replace the conversation history with one exported by `export_history`.
+    ///
+    /// # Arguments
+    ///
+    /// * `json` - A JSON array of messages.
+    pub fn import_history(&mut self, json: &str) -> Result<(), ClientError> {
+        self.prompt = serde_json::from_str(json).map_err(|e| ClientError::Deserialize(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Estimate the number of prompt tokens the conversation history will use.
+    ///
+    /// Includes the per-message overhead of the chat format.
+    /// The count is a heuristic unless the `tokens` feature is enabled.
+    pub fn estimate_tokens(&self) -> usize {
+        tokenizer::estimate_prompt_tokens(&self.prompt)
+    }
+
+    /// Drop the oldest messages until the estimated prompt size fits in the token budget.
+    ///
+    /// `System` and `Developer` messages are always kept. Tool results left without the
+    /// assistant message that requested them are dropped as well, since the API rejects them.
+    /// The token count is estimated with `tokenizer::estimate_prompt_tokens`.
     ///
     /// # Arguments
     ///
-    /// * `messages` - A vector of messages to add.
+    /// * `max_tokens` - The token budget of the prompt.
     ///
     /// # Returns
     ///
-    /// A mutable reference to self.
-    pub async fn add(&mut self, messages: Vec<Message>) -> &mut Self {
-        if let Some(limit) = self.entry_limit {
-            while self.prompt.len() as u64 + messages.len() as u64 > limit {
-                self.prompt.pop_front();
-            }
-        }
-        self.prompt.extend(messages);
-        self
-    }
-
-    pub async fn add_last(&mut self, messages: Vec<Message>) -> &mut Self {
-        if let Some(limit) = self.entry_limit {
-            while self.prompt.len() as u64 + messages.len() as u64 > limit {
-                self.prompt.pop_front();
+    /// The number of messages dropped.
+    pub fn trim_to_tokens(&mut self, max_tokens: usize) -> usize {
+        let mut tokens = tokenizer::estimate_prompt_tokens(&self.prompt);
+        let mut dropped = 0;
+        while tokens > max_tokens {
+            let pos = match self
+                .prompt
+                .iter()
+                .position(|message| !matches!(message, Message::System { .. } | Message::Developer { .. }))
+            {
+                Some(pos) => pos,
+                None => break,
+            };
+            while let Some(message) = self.prompt.remove(pos) {
+                tokens -= tokenizer::estimate_message_tokens(&message);
+                dropped += 1;
+                if !matches!(self.prompt.get(pos), Some(Message::Tool { .. })) {
+                    break;
+                }
             }
         }
-        for msg in messages {
-            self.prompt.push_front(msg);
-        }
-        self
+        dropped
     }
 
     /// Set the maximum number of entries in the conversation prompt.
@@ -454,22 +2141,49 @@ impl<'a> OpenAIClientState {
     /// A mutable reference to self.
     pub async fn set_entry_limit(&mut self, limit: u64) -> &mut Self {
         self.entry_limit = Some(limit);
-        while self.prompt.len() as u64 > limit {
-            self.prompt.pop_front();
-        }
+        self.make_room(0);
         self
     }
 
     /// Clear all messages from the conversation prompt.
     ///
+    /// The system prompt set with `set_system_prompt` is kept.
+    ///
     /// # Returns
     ///
     /// A mutable reference to self.
     pub async fn clear(&mut self) -> &mut Self {
         self.prompt.clear();
+        if let Some(system_prompt) = self.system_prompt.clone() {
+            self.set_system_prompt(&system_prompt);
+        }
         self
     }
 
+    /// Remove the message at the given index from the conversation prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The index of the message, counted from the oldest message.
+    ///
+    /// # Returns
+    ///
+    /// The removed message, or None if the index is out of range.
+    pub fn remove(&mut self, index: usize) -> Option<Message> {
+        self.prompt.remove(index)
+    }
+
+    /// Remove the last message from the conversation prompt.
+    ///
+    /// Useful to delete the last assistant reply and generate it again.
+    ///
+    /// # Returns
+    ///
+    /// The removed message, or None if the prompt is empty.
+    pub fn pop_last(&mut self) -> Option<Message> {
+        self.prompt.pop_back()
+    }
+
     /// Retrieve the last message in the prompt.
     ///
     /// # Returns
@@ -479,6 +2193,27 @@ impl<'a> OpenAIClientState {
         self.prompt.back()
     }
 
+    /// Retrieve the text of the most recent assistant message.
+    ///
+    /// # Returns
+    ///
+    /// The concatenated text parts of the last `Message::Assistant`,
+    /// or None if there is no assistant message or it has no text.
+    pub fn last_assistant_text(&self) -> Option<String> {
+        let content = self.prompt.iter().rev().find_map(|message| match message {
+            Message::Assistant { content, .. } => Some(content),
+            _ => None,
+        })?;
+        let text: String = content
+            .iter()
+            .filter_map(|context| match context {
+                MessageContext::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        if text.is_empty() { None } else { Some(text) }
+    }
+
     /// Generate an AI response.
     ///
     /// This method sends the prompt to the API and, upon successful response,
@@ -492,47 +2227,169 @@ impl<'a> OpenAIClientState {
     ///
     /// An APIResult with the API response or a ClientError.
     pub async fn generate(&mut self, model: Option<&ModelConfig>) -> Result<GenerateResponse, ClientError> {
+        self.generate_cancellable(model, None).await
+    }
+
+    /// Generate an AI response with some fields of the client's model configuration changed.
+    ///
+    /// Same as `generate`, with `overrides` merged onto the client's configuration for this call only.
+    ///
+    /// # Arguments
+    ///
+    /// * `overrides` - The fields to replace.
+    ///
+    /// # Returns
+    ///
+    /// A GenerateResponse or a ClientError.
+    pub async fn generate_with_overrides(&mut self, overrides: &ModelConfigOverride) -> Result<GenerateResponse, ClientError> {
+        let model = self
+            .client
+            .model_config
+            .as_ref()
+            .ok_or(ClientError::ModelConfigNotSet)?
+            .with_overrides(overrides);
+        self.generate(Some(&model)).await
+    }
+
+    /// Generate an AI response and return only its text.
+    ///
+    /// Same as `generate`; the assistant's message is still added to the prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model configuration.
+    ///
+    /// # Returns
+    ///
+    /// The assistant's text or a ClientError.
+    pub async fn generate_text(&mut self, model: Option<&ModelConfig>) -> Result<String, ClientError> {
+        let response = self.generate(model).await?;
+        response.content.ok_or(ClientError::UnknownError)
+    }
+
+    /// Generate an AI response that can be cancelled.
+    ///
+    /// Same as `generate`, but when `cancel` is cancelled the in-flight request is dropped
+    /// and `ClientError::Cancelled` is returned. The prompt is left untouched in that case.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model configuration.
+    /// * `cancel` - Optional token to cancel the request.
+    ///
+    /// # Returns
+    ///
+    /// An APIResult with the API response or a ClientError.
+    pub async fn generate_cancellable(&mut self, model: Option<&ModelConfig>, cancel: Option<&CancellationToken>) -> Result<GenerateResponse, ClientError> {
         // Retrieve model configuration: use provided model or fallback to the client's config.
-        let model = model.unwrap_or(
-            self.client
-                .model_config
-                .as_ref()
-                .ok_or(ClientError::ModelConfigNotSet)?
-        );
+        let model = model
+            .or(self.client.model_config.as_ref())
+            .ok_or(ClientError::ModelConfigNotSet)?;
+        let model_name = model.model_name.clone();
 
         // Send the request and extract the first choice.
-        let result = self.client.send(&self.prompt, Some(model)).await?;
-        let choice = result
-            .response
-            .choices
-            .as_ref()
-            .and_then(|choices| choices.first())
-            .ok_or(ClientError::InvalidResponse)?;
+        let result = until_cancelled(cancel, self.complete(model, ToolChoice::None)).await?;
+        self.record_usage(&result);
+        let response = GenerateResponse::from_result(result, self.client.error_on_truncation)?;
 
         // Ensure there is content in the assistant's reply.
-        let content = choice
-            .message
-            .content
-            .as_ref()
-            .ok_or(ClientError::UnknownError)?;
+        let content = response.content.clone().ok_or(ClientError::UnknownError)?;
 
         // Add the assistant's message to the conversation.
         self.add(vec![Message::Assistant {
-            name: model.model_name.clone(),
-            content: vec![MessageContext::Text(content.clone())],
+            name: model_name,
+            content: vec![MessageContext::Text(content)],
             tool_calls: None,
+            created_at: None,
+            metadata: None,
         }])
         .await;
 
-        Ok(
-            GenerateResponse {
-                has_content: true,
-                has_tool_calls: false,
-                content: Some(content.clone()),
-                tool_calls: None,
-                api_result: result,
-            }
-        )
+        Ok(response)
+    }
+
+    /// Generate multiple candidate responses without modifying the prompt.
+    ///
+    /// Set `ModelConfig::n` to the number of candidates to request.
+    /// Since it is ambiguous which candidate should continue the conversation,
+    /// nothing is added to the prompt; add the chosen one yourself.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model configuration.
+    ///
+    /// # Returns
+    ///
+    /// The text content of every choice ordered by index, or `ClientError::InvalidChoice`
+    /// if any choice has no content (e.g. it contains tool calls).
+    pub async fn generate_n(&self, model: Option<&ModelConfig>) -> Result<Vec<String>, ClientError> {
+        let model = model.or(self.client.model_config.as_ref()).ok_or(ClientError::ModelConfigNotSet)?;
+
+        let result = self.complete(model, ToolChoice::None).await?;
+        // Fail on an empty `choices` instead of returning an empty list.
+        result.first_choice()?;
+        let mut choices = result.response.choices.ok_or(ClientError::InvalidResponse)?;
+        choices.sort_by_key(|choice| choice.index);
+
+        choices
+            .into_iter()
+            .map(|choice| {
+                if let Some(refusal) = choice.message.refusal {
+                    return Err(ClientError::Refusal(refusal));
+                }
+                if choice.message.tool_calls.is_some() {
+                    return Err(ClientError::InvalidChoice {
+                        index: choice.index,
+                        reason: "returned tool_calls instead of content".to_string(),
+                    });
+                }
+                choice.message.content.ok_or(ClientError::InvalidChoice {
+                    index: choice.index,
+                    reason: "has no content".to_string(),
+                })
+            })
+            .collect()
+    }
+
+    /// Generate an AI response and return the text of every choice.
+    ///
+    /// Like `generate`, only the first choice is added to the prompt.
+    /// Set `ModelConfig::n` to the number of choices to request.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model configuration.
+    ///
+    /// # Returns
+    ///
+    /// The text content of every choice ordered by index, or a ClientError.
+    pub async fn generate_choices(&mut self, model: Option<&ModelConfig>) -> Result<Vec<String>, ClientError> {
+        let response = self.generate(model).await?;
+        let mut choices = response.api_result.response.choices.unwrap_or_default();
+        choices.sort_by_key(|choice| choice.index);
+
+        Ok(choices
+            .into_iter()
+            .filter_map(|choice| choice.message.content)
+            .collect())
+    }
+
+    /// Generate an AI response and deserialize its content into `T`.
+    ///
+    /// Intended to be used with `ModelConfig::response_format` set to JSON mode or a JSON schema.
+    /// The raw assistant message is added to the prompt even if deserialization fails.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model configuration.
+    ///
+    /// # Returns
+    ///
+    /// The deserialized content, or `ClientError::Deserialize` if it does not match `T`.
+    pub async fn generate_typed<T: DeserializeOwned>(&mut self, model: Option<&ModelConfig>) -> Result<T, ClientError> {
+        let response = self.generate(model).await?;
+        let content = response.content.ok_or(ClientError::UnknownError)?;
+        serde_json::from_str(&content).map_err(|e| ClientError::Deserialize(e.to_string()))
     }
 
     /// Generate an AI response, possibly calling a tool.
@@ -548,63 +2405,80 @@ impl<'a> OpenAIClientState {
     ///
     /// An APIResult with the API response or a ClientError.
     pub async fn generate_can_use_tool<F>(&mut self, model: Option<&ModelConfig>, show_call: Option<F>) -> Result<GenerateResponse, ClientError>
+    where F: Fn(&str, &serde_json::Value) { 
+        self.generate_can_use_tool_cancellable(model, show_call, None).await
+    }
+
+    async fn generate_can_use_tool_cancellable<F>(&mut self, model: Option<&ModelConfig>, show_call: Option<F>, cancel: Option<&CancellationToken>) -> Result<GenerateResponse, ClientError>
     where F: Fn(&str, &serde_json::Value) { 
         // Use the provided model configuration or fallback to the client's configuration.
         let model = model.or(self.client.model_config.as_ref()).ok_or(ClientError::ModelConfigNotSet)?;
+        let model_name = model.model_name.clone();
 
         // Send the request with "can use tool" mode.
-        let result = self.client.send_can_use_tool(&self.prompt, Some(model)).await?;
-        let choices = result
-            .response
-            .choices
-            .as_ref()
-            .ok_or(ClientError::InvalidResponse)?;
-        
-        let choice = choices.first().ok_or(ClientError::InvalidResponse)?;
-        let has_content = choice.message.content.is_some();
-        let has_tool_calls = choice.message.tool_calls.is_some();
+        let result = until_cancelled(cancel, self.complete(model, ToolChoice::Auto)).await?;
+        self.record_usage(&result);
+        let response = GenerateResponse::from_result(result, self.client.error_on_truncation)?;
 
         // Ensure that there is either content or a tool call.
-        if !has_content && !has_tool_calls {
+        if !response.has_content && !response.has_tool_calls {
             return Err(ClientError::UnknownError);
         }
 
-        // If content is returned, add the assistant message.
-        self.add(vec![Message::Assistant {
-            name: model.model_name.clone(),
-            content: if has_content { vec![MessageContext::Text(choice.message.content.clone().unwrap())] } else { vec![] },
-            tool_calls: choice.message.tool_calls.clone(),
-        }]).await;
+        self.handle_tool_calls(&response, model_name, show_call.as_ref()).await?;
+        Ok(response)
+    }
 
-        // Process any tool calls.
-        if let Some(tool_calls) = &choice.message.tool_calls {
-            for call in tool_calls {
-                let (tool, enabled) = self.client.tools
-                    .get(&call.function.name)
-                    .ok_or(ClientError::ToolNotFound)?;
-                if !*enabled {
-                    return Err(ClientError::ToolNotFound);
-                }
-                if let Some(show_call) = &show_call {
-                    show_call(&call.function.name, &call.function.arguments);
-                }
-                let result_text = tool
-                    .run(call.function.arguments.clone())
-                    .unwrap_or_else(|e| format!("Error: {}", e));
-                self.add(vec![Message::Tool {
-                    tool_call_id: call.id.clone(),
-                    content: vec![MessageContext::Text(result_text)],
-                }]).await;
+    /// Generate an AI response, running tools until the model gives a final answer.
+    ///
+    /// Repeats `generate_can_use_tool`, feeding the tool results back to the model,
+    /// until the assistant replies without tool calls.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model configuration.
+    /// * `max_iterations` - The maximum number of requests to send.
+    ///
+    /// # Returns
+    ///
+    /// The final GenerateResponse, or `ClientError::MaxIterationsExceeded` if the model
+    /// is still requesting tools after `max_iterations` requests.
+    pub async fn generate_auto(&mut self, model: Option<&ModelConfig>, max_iterations: usize) -> Result<GenerateResponse, ClientError> {
+        self.generate_auto_cancellable(model, max_iterations, None).await
+    }
+
+    async fn generate_auto_cancellable(&mut self, model: Option<&ModelConfig>, max_iterations: usize, cancel: Option<&CancellationToken>) -> Result<GenerateResponse, ClientError> {
+        for _ in 0..max_iterations {
+            let response = self
+                .generate_can_use_tool_cancellable::<fn(&str, &serde_json::Value)>(model, None, cancel)
+                .await?;
+            if !response.has_tool_calls {
+                return Ok(response);
             }
         }
+        Err(ClientError::MaxIterationsExceeded)
+    }
 
-        Ok(GenerateResponse {
-            has_content,
-            has_tool_calls,
-            content: choice.message.content.clone(),
-            tool_calls: choice.message.tool_calls.clone(),
-            api_result: result,
-        })
+    /// Run the tool-calling loop until completion and return the final answer.
+    ///
+    /// Same as `generate_auto`, but returns only the assistant's final text.
+    ///
+    /// When `cancel` is cancelled, the in-flight request is dropped and `ClientError::Cancelled`
+    /// is returned. Tools that are already running are completed so that the prompt stays consistent.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model configuration.
+    /// * `max_rounds` - The maximum number of requests to send.
+    /// * `cancel` - Optional token to cancel the generation.
+    ///
+    /// # Returns
+    ///
+    /// The final assistant content, or `ClientError::MaxIterationsExceeded` if the model
+    /// is still requesting tools after `max_rounds` requests.
+    pub async fn run_to_completion(&mut self, model: Option<&ModelConfig>, max_rounds: usize, cancel: Option<&CancellationToken>) -> Result<String, ClientError> {
+        let response = self.generate_auto_cancellable(model, max_rounds, cancel).await?;
+        response.content.ok_or(ClientError::UnknownError)
     }
 
     /// Generate an AI response while forcing the use of a specific tool.
@@ -619,73 +2493,23 @@ impl<'a> OpenAIClientState {
     /// 
     /// # Returns
     /// 
-    /// An APIResult with the API response or a ClientError.
+    /// An APIResult with the API response, `ClientError::NoToolCall` if the model did not call a tool, or another ClientError.
     pub async fn generate_use_tool<F>(&mut self, model: Option<&ModelConfig>, show_call: Option<F>) -> Result<GenerateResponse, ClientError>
     where F: Fn(&str, &serde_json::Value) {
-        let model = model.unwrap_or(
-            self.client
-                .model_config
-                .as_ref()
-                .ok_or(ClientError::ModelConfigNotSet)?
-        );
-
-        let result = self.client.send_use_tool(&self.prompt, Some(model)).await?;
-        let choices = result
-            .response
-            .choices
-            .as_ref()
-            .ok_or(ClientError::InvalidResponse)?;
+        let model = model.or(self.client.model_config.as_ref()).ok_or(ClientError::ModelConfigNotSet)?;
+        let model_name = model.model_name.clone();
 
-        let choice = choices.first().ok_or(ClientError::InvalidResponse)?;
-        let content = choice.message.content.clone();
-        let tool_calls = choice.message.tool_calls.clone();
+        let result = self.complete(model, ToolChoice::Required).await?;
+        self.record_usage(&result);
+        let response = GenerateResponse::from_result(result, self.client.error_on_truncation)?;
 
         // If there is no tool call, return an error.
-        if tool_calls.is_none() {
-            return Err(ClientError::ToolNotFound);
-        }
-
-        let has_content = content.is_some();
-
-        // Add the assistant's reply to the conversation.
-        self.add(vec![Message::Assistant {
-            name: model.model_name.clone(),
-            content: if has_content { vec![MessageContext::Text(choice.message.content.clone().unwrap())] } else { vec![] },
-            tool_calls: choice.message.tool_calls.clone(),
-        }]).await;
-
-        // Process any tool calls.
-        if let Some(calls) = tool_calls.clone() {
-            for call in calls {
-                let (tool, enabled) = self
-                    .client
-                    .tools
-                    .get(&call.function.name)
-                    .ok_or(ClientError::ToolNotFound)?;
-                if !*enabled {
-                    return Err(ClientError::ToolNotFound);
-                }
-                if let Some(show_call) = &show_call {
-                    show_call(&call.function.name, &call.function.arguments);
-                }
-                let result_text = match tool.run(call.function.arguments.clone()) {
-                    Ok(res) => res,
-                    Err(e) => format!("Error: {}", e),
-                };
-                self.add(vec![Message::Tool {
-                    tool_call_id: call.id.clone(),
-                    content: vec![MessageContext::Text(result_text)],
-                }]).await;
-            }
+        if !response.has_tool_calls {
+            return Err(ClientError::NoToolCall);
         }
 
-        Ok(GenerateResponse {
-            has_content,
-            has_tool_calls: true,
-            content,
-            tool_calls,
-            api_result: result,
-        })
+        self.handle_tool_calls(&response, model_name, show_call.as_ref()).await?;
+        Ok(response)
     }
 
     /// Generate an AI response while forcing the use of a specific tool.
@@ -700,82 +2524,236 @@ impl<'a> OpenAIClientState {
     ///
     /// # Returns
     ///
-    /// An APIResult with the API response or a ClientError.
+    /// An APIResult with the API response, `ClientError::NoToolCall` if the model did not call a tool, or another ClientError.
     pub async fn generate_with_tool<F>(&mut self, model: Option<&ModelConfig>, tool_name: &str, show_call: Option<F>) -> Result<GenerateResponse, ClientError>
     where F: Fn(&str, &serde_json::Value) {
-        let model = model.unwrap_or(
-            self.client.model_config.as_ref().ok_or(ClientError::ModelConfigNotSet)?
-        );
-
-        let result = self.client.send_with_tool(&self.prompt, tool_name, Some(model)).await?;
-        let choices = result
-            .response
-            .choices
-            .as_ref()
-            .ok_or(ClientError::InvalidResponse)?;
+        let model = model.or(self.client.model_config.as_ref()).ok_or(ClientError::ModelConfigNotSet)?;
+        let model_name = model.model_name.clone();
 
-        let choice = choices.first().ok_or(ClientError::InvalidResponse)?;
-        let content = choice.message.content.clone();
-        let tool_calls = choice.message.tool_calls.clone();
+        let result = self.complete(model, ToolChoice::Function(tool_name.to_string())).await?;
+        self.record_usage(&result);
+        let response = GenerateResponse::from_result(result, self.client.error_on_truncation)?;
 
         // If there is no tool call, return an error.
-        if tool_calls.is_none() {
-            return Err(ClientError::ToolNotFound);
+        if !response.has_tool_calls {
+            return Err(ClientError::NoToolCall);
         }
 
-        let has_content = content.is_some();
+        self.handle_tool_calls(&response, model_name, show_call.as_ref()).await?;
+        Ok(response)
+    }
 
-        // Add the assistant's reply to the conversation.
-        self.add(vec![Message::Assistant {
-            name: model.model_name.clone(),
-            content: if has_content { vec![MessageContext::Text(choice.message.content.clone().unwrap())] } else { vec![] },
-            tool_calls: choice.message.tool_calls.clone(),
-        }]).await;
+    /// Run the tool calls the assistant's reply requests and add both to the conversation.
+    ///
+    /// The tools are run first, so nothing is added if one of them is not found or disabled;
+    /// the prompt never holds tool calls without their results.
+    ///
+    /// Shared by `generate_can_use_tool`, `generate_use_tool` and `generate_with_tool`.
+    async fn handle_tool_calls<F>(&mut self, response: &GenerateResponse, model_name: Option<String>, show_call: Option<&F>) -> Result<(), ClientError>
+    where F: Fn(&str, &serde_json::Value) {
+        let results = match &response.tool_calls {
+            Some(tool_calls) => self.client.run_tool_calls(tool_calls, show_call).await?,
+            None => vec![],
+        };
 
-        // Process any tool calls.
-        if let Some(calls) = tool_calls.clone() {
-            for call in calls {
-                let (tool, enabled) = self
-                    .client
-                    .tools
-                    .get(&call.function.name)
-                    .ok_or(ClientError::ToolNotFound)?;
-                if !*enabled {
-                    return Err(ClientError::ToolNotFound);
-                }
-                if let Some(show_call) = &show_call {
-                    show_call(&call.function.name, &call.function.arguments);
-                }
-                let result_text = match tool.run(call.function.arguments.clone()) {
-                    Ok(res) => res,
-                    Err(e) => format!("Error: {}", e),
-                };
-                self.add(vec![Message::Tool {
-                    tool_call_id: call.id.clone(),
-                    content: vec![MessageContext::Text(result_text)],
-                }]).await;
+        let mut messages = vec![Message::Assistant {
+            name: model_name,
+            content: response.content.iter().cloned().map(MessageContext::Text).collect(),
+            tool_calls: response.tool_calls.clone(),
+            created_at: None,
+            metadata: None,
+        }];
+        messages.extend(results);
+        self.add(messages).await;
+        Ok(())
+    }
+}
+
+/// Streaming is specific to the OpenAI compatible API.
+impl OpenAIClientState {
+    /// Generate an AI response as a stream of incremental chunks.
+    ///
+    /// The request is sent with tool auto-selection. Each item of the returned stream
+    /// is a `ChatDelta` carrying the role (first chunk only), a content fragment and
+    /// any partial tool call fragments. When the stream finishes, the assembled
+    /// assistant message is added to the prompt just like `generate` does.
+    /// If the model requested tool calls, the message is only added once they have been
+    /// run with `ChatStream::run_tool_calls`, so the prompt never holds unanswered tool calls.
+    ///
+    /// If the stream fails midway, the message collected so far can still be
+    /// retrieved with `ChatStream::partial_message`.
+    ///
+    /// When `cancel` is cancelled, the stream stops reading the response, drops the
+    /// connection and yields `ClientError::Cancelled`. The text yielded so far is
+    /// added to the prompt as the assistant message; partial tool calls are dropped.
+    ///
+    /// If the connection closes before the server sent a finish reason or `[DONE]`, the stream
+    /// yields `ClientError::NetworkError` and the prompt is left unchanged.
+    ///
+    /// The API only reports the token usage of a stream when `ModelConfig::stream_options`
+    /// sets `include_usage`. It is then available from `ChatStream::usage` once the stream
+    /// has finished, and is added to `total_usage`.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model configuration.
+    /// * `cancel` - Optional token to cancel the generation.
+    ///
+    /// # Returns
+    ///
+    /// A ChatStream yielding `Result<ChatDelta, ClientError>` or a ClientError.
+    /// `ClientError::InvalidInput` if `n` is greater than 1.
+    pub async fn generate_stream(&mut self, model: Option<&ModelConfig>, cancel: Option<&CancellationToken>) -> Result<ChatStream<'_>, ClientError> {
+        let model = model.or(self.client.model_config.as_ref()).ok_or(ClientError::ModelConfigNotSet)?.clone();
+        if model.n.is_some_and(|n| n > 1) {
+            return Err(ClientError::InvalidInput("streaming supports only one choice (n = 1)".to_string()));
+        }
+
+        let backend = self.backend.as_ref().unwrap_or(&self.client);
+        let res = until_cancelled(cancel, backend.call_api_stream(
+            &self.prompt,
+            Some(&ToolChoice::Auto),
+            Some(&model),
+        )).await?;
+
+        Ok(ChatStream::new(self, model.model_name, res, cancel.cloned()))
+    }
+}
+
+/// Build an HTTP client with the given timeouts and TLS settings.
+///
+/// Shared by `OpenAIClient` and `AnthropicClient`.
+pub(crate) fn build_http_client(timeout: Option<Duration>, connect_timeout: Option<Duration>, accept_invalid_certs: bool) -> Result<Client, ClientError> {
+    let mut builder = Client::builder();
+    if accept_invalid_certs {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(timeout) = connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    builder
+        .build()
+        .map_err(|e| ClientError::InvalidInput(format!("failed to build the HTTP client: {}", e)))
+}
+
+/// Convert custom headers, skipping invalid names or values.
+pub(crate) fn custom_headers(headers: &HashMap<String, String>) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (key, value) in headers {
+        match (HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(value)) {
+            (Ok(name), Ok(value)) => {
+                map.insert(name, value);
             }
+            _ => log::warn!("Skipping invalid header: {}", key),
         }
+    }
+    map
+}
 
-        Ok(
-            GenerateResponse {
-                has_content,
-                has_tool_calls: true,
-                content,
-                tool_calls,
-                api_result: result,
+/// Send a request, retrying according to the retry policy.
+///
+/// `request` builds the request; it is called again for each attempt.
+/// Shared by `OpenAIClient` and `AnthropicClient`.
+pub(crate) async fn send_with_retry<F>(retry_config: Option<&RetryConfig>, request: F) -> Result<Response, ClientError>
+where F: Fn() -> RequestBuilder {
+    let mut attempt = 0;
+    loop {
+        let res = request()
+            .send()
+            .await;
+
+        // Retry on transient network errors such as connection resets.
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => match retry_config {
+                Some(retry) if attempt < retry.max_retries && is_transient(&e) => {
+                    let delay = retry.delay(attempt, None);
+                    log::debug!("Request failed with {}, retrying in {:?}", e, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                    continue;
+                }
+                _ => return Err(ClientError::from_reqwest(e)),
+            },
+        };
+
+        // Retry on rate limiting and server errors if a retry policy is configured.
+        let status = res.status();
+        let retry = match retry_config {
+            Some(retry) if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() => retry,
+            _ => return Ok(res),
+        };
+
+        let headers = APIResponseHeaders::from_headers(res.headers());
+        // A wait longer than `max_delay` is left to the caller.
+        let wait_too_long = retry.respect_retry_after
+            && headers.retry_after.is_some_and(|secs| Duration::from_secs(secs) > retry.max_delay);
+        if attempt >= retry.max_retries || wait_too_long {
+            if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE {
+                return Err(ClientError::RateLimited {
+                    retry_after: headers.retry_after,
+                    reset: headers.reset,
+                });
             }
-        )
+            return Ok(res);
+        }
+
+        let delay = retry.delay(attempt, headers.retry_after);
+        log::debug!("Request failed with status {}, retrying in {:?}", status, delay);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Whether a failed request may succeed when sent again.
+///
+/// Connection failures, timeouts and connections reset by the server are transient.
+/// Errors such as an invalid URL fail the same way on every attempt.
+fn is_transient(err: &reqwest::Error) -> bool {
+    if err.is_connect() || err.is_timeout() {
+        return true;
+    }
+    let mut source = std::error::Error::source(err);
+    while let Some(e) = source {
+        if let Some(io) = e.downcast_ref::<std::io::Error>() {
+            return matches!(
+                io.kind(),
+                std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted | std::io::ErrorKind::BrokenPipe
+            );
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Await `future`, returning `ClientError::Cancelled` as soon as `cancel` is cancelled.
+///
+/// The future is dropped on cancellation, which aborts the underlying HTTP request.
+async fn until_cancelled<T>(
+    cancel: Option<&CancellationToken>,
+    future: impl std::future::Future<Output = Result<T, ClientError>>,
+) -> Result<T, ClientError> {
+    match cancel {
+        Some(cancel) => tokio::select! {
+            biased;
+            _ = cancel.cancelled() => Err(ClientError::Cancelled),
+            result = future => result,
+        },
+        None => future.await,
     }
 }
 
-pub struct ReasoningState<'a> {
-    pub state: &'a mut OpenAIClientState,
+pub struct ReasoningState<'a, B: ChatBackend = OpenAIClient> {
+    pub state: &'a mut OpenAIClientState<B>,
     pub model: ModelConfig,
     pub has_content: bool,
     pub has_tool_calls: bool,
     pub content: Option<String>,
     pub tool_calls: Option<Vec<FunctionCall>>,
+    pub finish_reason: FinishReason,
     pub api_result: APIResult,
 }
 
@@ -789,45 +2767,51 @@ pub enum ToolMode {
 }
 
 /// new api after v.1.4.0
-impl<'a> OpenAIClientState {
-    pub async fn reasoning(&'a mut self, model: Option<&ModelConfig>, mode: &ToolMode) -> Result<ReasoningState<'a>, ClientError> {
-        let model = model.unwrap_or(
-            self.client.model_config.as_ref().ok_or(ClientError::ModelConfigNotSet)?
-        ).clone();
+impl<'a, B: ChatBackend> OpenAIClientState<B> {
+    pub async fn reasoning(&'a mut self, model: Option<&ModelConfig>, mode: &ToolMode) -> Result<ReasoningState<'a, B>, ClientError> {
+        let model = model.or(self.client.model_config.as_ref()).ok_or(ClientError::ModelConfigNotSet)?.clone();
 
         let result = match &mode {
-            ToolMode::Disable => self.client.send(&self.prompt, Some(&model)).await?,
-            ToolMode::Auto => self.client.send_can_use_tool(&self.prompt, Some(&model)).await?,
-            ToolMode::Force(tool_name) => self.client.send_with_tool(&self.prompt, &tool_name, Some(&model)).await?,
+            ToolMode::Disable => self.complete(&model, ToolChoice::None).await?,
+            ToolMode::Auto => self.complete(&model, ToolChoice::Auto).await?,
+            ToolMode::Force(tool_name) => self.complete(&model, ToolChoice::Function(tool_name.clone())).await?,
         };
+        self.record_usage(&result);
 
-        let choices = result.response.choices.as_ref().ok_or(ClientError::InvalidResponse)?;
-        let choice = choices.first().ok_or(ClientError::InvalidResponse)?;
+        let choice = result.first_choice()?;
         let content = choice.message.content.clone();
         let tool_calls = choice.message.tool_calls.clone();
 
         let has_content = content.is_some();
 
+        // The calls are run by `proceed`; refuse them now rather than leave them unanswered in the prompt.
+        if let Some(tool_calls) = &tool_calls {
+            self.client.check_tool_calls(tool_calls)?;
+        }
+
         // Add the assistant's reply to the conversation.
         self.add(vec![Message::Assistant {
             name: model.model_name.clone(),
             content: if has_content { vec![MessageContext::Text(choice.message.content.clone().unwrap())] } else { vec![] },
             tool_calls: choice.message.tool_calls.clone(),
+            created_at: None,
+            metadata: None,
         }]).await;
 
         Ok(ReasoningState {
             state: &mut *self,
-            model: model,
+            model,
             has_content,
             has_tool_calls: tool_calls.is_some(),
             content,
             tool_calls,
+            finish_reason: choice.finish_reason.clone(),
             api_result: result,
         })
     }
 }
 
-impl<'a> ReasoningState<'a> {
+impl<B: ChatBackend> ReasoningState<'_, B> {
     /// Check if the reasoning state can proceed.
     pub fn can_finish(&self) -> bool {
         self.has_content && !self.has_tool_calls
@@ -852,48 +2836,42 @@ impl<'a> ReasoningState<'a> {
     /// - A Result indicating success or failure.
     pub async fn proceed(&mut self, mode: &ToolMode) -> Result<(), ClientError> {
         if let Some(tool_calls) = &self.tool_calls {
-            for call in tool_calls {
-                let (tool, enabled) = self.state.client.tools
-                    .get(&call.function.name)
-                    .ok_or(ClientError::ToolNotFound)?;
-                if !*enabled {
-                    return Err(ClientError::ToolNotFound);
-                }
-                let result_text = match tool.run(call.function.arguments.clone()) {
-                    Ok(res) => res,
-                    Err(e) => format!("Error: {}", e),
-                };
-                self.state.add(vec![Message::Tool {
-                    tool_call_id: call.id.clone(),
-                    content: vec![MessageContext::Text(result_text)],
-                }]).await;
-            }
+            let results = self.state.client
+                .run_tool_calls::<fn(&str, &serde_json::Value)>(tool_calls, None)
+                .await?;
+            self.state.add(results).await;
         }
 
         let result = match mode {
-            ToolMode::Disable => self.state.client.send(&self.state.prompt, Some(&self.model)).await?,
-            ToolMode::Auto => self.state.client.send_can_use_tool(&self.state.prompt, Some(&self.model)).await?,
-            ToolMode::Force(tool_name) => self.state.client.send_with_tool(&self.state.prompt, tool_name, Some(&self.model)).await?,
+            ToolMode::Disable => self.state.complete(&self.model, ToolChoice::None).await?,
+            ToolMode::Auto => self.state.complete(&self.model, ToolChoice::Auto).await?,
+            ToolMode::Force(tool_name) => self.state.complete(&self.model, ToolChoice::Function(tool_name.clone())).await?,
         };
+        self.state.record_usage(&result);
 
-        let choices = result.response.choices.as_ref().ok_or(ClientError::InvalidResponse)?;
-        let choice = choices.first().ok_or(ClientError::InvalidResponse)?;
+        let choice = result.first_choice()?;
         let content = choice.message.content.clone();
         let tool_calls = choice.message.tool_calls.clone();
 
         let has_content = content.is_some();
 
+        if let Some(tool_calls) = &tool_calls {
+            self.state.client.check_tool_calls(tool_calls)?;
+        }
 
         self.state.add(vec![Message::Assistant {
             name: self.model.model_name.clone(),
             content: if has_content { vec![MessageContext::Text(choice.message.content.clone().unwrap())] } else { vec![] },
             tool_calls: choice.message.tool_calls.clone(),
+            created_at: None,
+            metadata: None,
         }]).await;
 
         self.has_content = has_content;
         self.has_tool_calls = tool_calls.is_some();
         self.content = content;
         self.tool_calls = tool_calls;
+        self.finish_reason = choice.finish_reason.clone();
         self.api_result = result;
         Ok(())
     }
@@ -11,12 +11,60 @@ pub enum ClientError {
     /// I/O操作中のエラー
     IoError(std::io::Error),
     IndexOutOfBounds,
-    ToolNotFound,
+    /// 指定されたツールが登録されていない場合(ツール名を保持)
+    ToolNotFound(String),
+    /// 指定されたツールが無効化されている場合(ツール名を保持)
+    ToolDisabled(String),
+    /// ツールの使用を要求したのにモデルがツールを呼ばなかった場合
+    NoToolCall,
     InvalidEndpoint,
     InvalidPrompt,
     NetworkError,
+    /// リクエストがタイムアウトした場合
+    Timeout,
     InvalidResponse,
+    /// レスポンスの内容を指定された型にデシリアライズできなかった場合
+    Deserialize(String),
     ModelConfigNotSet,
+    /// ツール呼び出しのループが上限回数に達した場合
+    MaxIterationsExceeded,
+    /// リトライ上限に達してもレート制限(429)またはサービス停止(503)が解除されなかった場合  
+    /// またはRetry-Afterが`RetryConfig::max_delay`より長い場合
+    RateLimited {
+        /// Retry-After header value (in seconds)
+        retry_after: Option<u64>,
+        /// X-RateLimit-Reset header value
+        reset: Option<u64>,
+    },
+    /// APIがエラーを返した場合
+    ApiError {
+        /// Error message text
+        message: String,
+        /// Error type (e.g. "invalid_request_error")
+        err_type: String,
+        /// Error code (e.g. "invalid_api_key")
+        code: Option<String>,
+    },
+    /// モデルが応答を拒否した場合
+    Refusal(String),
+    /// 最大トークン数に達して出力が途中で切れた場合(途中までの内容を保持)
+    Truncated(String),
+    /// CancellationTokenによって生成が中断された場合
+    Cancelled,
+    /// レスポンスのchoicesが空だった場合(ゲートウェイの過負荷時など)
+    NoChoices {
+        /// `object` of the response
+        object: String,
+        /// `model` of the response
+        model: Option<String>,
+    },
+    /// レスポンスの特定のchoiceが期待した形式でなかった場合(テキストの代わりにtool_callsを返したなど)
+    InvalidChoice {
+        /// Index of the choice
+        index: usize,
+        /// What was wrong with the choice
+        reason: String,
+    },
     UnknownError,
 }
 
@@ -32,11 +80,24 @@ pub enum ClientError {
 /// - InvalidInput: Denotes that the provided input is not valid. The message explains the issue.
 /// - IoError: Wraps a standard I/O error, relaying the system error message.
 /// - IndexOutOfBounds: Indicates that an index is outside the allowable bounds.
-/// - ToolNotFound: Signals that a required external tool was not found.
+/// - ToolNotFound: Signals that a required external tool was not found. The message is the tool name.
+/// - ToolDisabled: Signals that the requested tool is registered but disabled. The message is the tool name.
+/// - NoToolCall: A tool call was required, but the model replied without calling a tool.
 /// - InvalidEndpoint: Denotes that a specified endpoint URL or address is invalid.
 /// - InvalidPrompt: Indicates that a provided prompt does not meet expected criteria.
 /// - NetworkError: Reflects issues with network connectivity or communication.
+/// - Timeout: The request did not complete within the configured timeout.
 /// - InvalidResponse: Indicates that the response received does not match the expected format.
+/// - Deserialize: The model output could not be deserialized into the requested type. The message carries the serde error.
+/// - MaxIterationsExceeded: The model was still calling tools when the iteration limit was reached.
+/// - RateLimited: The request was still rate limited (429) or the service unavailable (503) after exhausting all retries,
+///   or the server asked to wait longer than `RetryConfig::max_delay`.
+/// - ApiError: The API returned an error object, relaying its message, type and code.
+/// - Refusal: The model refused to respond. The message carries the refusal text.
+/// - Truncated: The output was cut off by the maximum token limit. The message carries the partial content.
+/// - Cancelled: The generation was cancelled through a `CancellationToken`.
+/// - NoChoices: The response had an empty `choices` array. The message includes the response `object` and `model`.
+/// - InvalidChoice: A choice of the response did not have the expected shape, e.g. tool calls instead of text.
 /// - UnknownError: A catch-all for errors that do not fit any of the other categories.
 ///
 /// These messages are intended for crate users and are provided in English to support clarity
@@ -48,12 +109,43 @@ impl fmt::Display for ClientError {
             ClientError::InvalidInput(ref msg) => write!(f, "InvalidInput: {}", msg),
             ClientError::IoError(ref err) => write!(f, "IoError: {}", err),
             ClientError::IndexOutOfBounds => write!(f, "Index out of bounds"),
-            ClientError::ToolNotFound => write!(f, "Tool not found"),
+            ClientError::ToolNotFound(ref name) => write!(f, "Tool not found: {}", name),
+            ClientError::ToolDisabled(ref name) => write!(f, "Tool disabled: {}", name),
+            ClientError::NoToolCall => write!(f, "The model did not call a tool"),
             ClientError::InvalidEndpoint => write!(f, "Invalid endpoint"),
             ClientError::InvalidPrompt => write!(f, "Invalid prompt"),
             ClientError::NetworkError => write!(f, "Network error"),
+            ClientError::Timeout => write!(f, "Request timed out"),
             ClientError::InvalidResponse => write!(f, "Invalid response"),
+            ClientError::Deserialize(ref msg) => write!(f, "Deserialize: {}", msg),
             ClientError::ModelConfigNotSet => write!(f, "Model config not set"),
+            ClientError::MaxIterationsExceeded => write!(f, "Maximum number of tool call iterations exceeded"),
+            ClientError::RateLimited { retry_after, reset } => {
+                write!(f, "Rate limited")?;
+                if let Some(retry_after) = retry_after {
+                    write!(f, " (retry after: {}s)", retry_after)?;
+                }
+                if let Some(reset) = reset {
+                    write!(f, " (reset: {})", reset)?;
+                }
+                Ok(())
+            }
+            ClientError::ApiError { message, err_type, code: Some(code) } => {
+                write!(f, "API error ({}, code {}): {}", err_type, code, message)
+            }
+            ClientError::ApiError { message, err_type, code: None } => {
+                write!(f, "API error ({}): {}", err_type, message)
+            }
+            ClientError::Refusal(ref msg) => write!(f, "Refusal: {}", msg),
+            ClientError::Truncated(ref content) => write!(f, "Truncated: output was cut off after {} characters", content.chars().count()),
+            ClientError::Cancelled => write!(f, "Cancelled"),
+            ClientError::NoChoices { ref object, ref model } => write!(
+                f,
+                "No choices in response (object: {}, model: {})",
+                object,
+                model.as_deref().unwrap_or("unknown")
+            ),
+            ClientError::InvalidChoice { index, ref reason } => write!(f, "Invalid choice {}: {}", index, reason),
             ClientError::UnknownError => write!(f, "Unknown error"),
         }
     }
@@ -79,4 +171,15 @@ impl From<String> for ClientError {
     fn from(err: String) -> Self {
         ClientError::InvalidInput(err)
     }
-}
\ No newline at end of file
+}
+
+impl ClientError {
+    /// Convert a reqwest error, distinguishing timeouts from other network errors.
+    pub(crate) fn from_reqwest(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            ClientError::Timeout
+        } else {
+            ClientError::NetworkError
+        }
+    }
+}
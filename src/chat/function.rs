@@ -1,8 +1,11 @@
 use std::fmt;
 
-use serde::{de::{self, Visitor}, Deserialize, Deserializer, Serialize, Serializer};
+use async_trait::async_trait;
+use serde::{de::{self, DeserializeOwned, Visitor}, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 
+use super::{err::ClientError, prompt::MessageContext};
+
 /// function call の定義  
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ToolDef {
@@ -47,6 +50,40 @@ pub struct FunctionCall {
     pub function: FunctionCallInner,
 }
 
+impl FunctionCall {
+    /// 引数を型付きで取得  
+    /// Deserialize the arguments into `T`.
+    ///
+    /// # Returns
+    ///
+    /// The arguments, or `ClientError::Deserialize` if they do not match `T`
+    /// (including arguments that were not valid JSON).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use call_agent::chat::function::{FunctionCall, FunctionCallInner};
+    /// #[derive(serde::Deserialize)]
+    /// struct Args {
+    ///     input: String,
+    /// }
+    ///
+    /// let call = FunctionCall {
+    ///     id: "call_1".to_string(),
+    ///     tool_type: "function".to_string(),
+    ///     function: FunctionCallInner {
+    ///         name: "text_length".to_string(),
+    ///         arguments: serde_json::json!({ "input": "Hello" }),
+    ///     },
+    /// };
+    /// let args: Args = call.parsed_args().unwrap();
+    /// assert_eq!(args.input, "Hello");
+    /// ```
+    pub fn parsed_args<T: DeserializeOwned>(&self) -> Result<T, ClientError> {
+        T::deserialize(&self.function.arguments).map_err(|e| ClientError::Deserialize(e.to_string()))
+    }
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct FunctionCallInner {
     /// 関数名  
@@ -59,6 +96,33 @@ pub struct FunctionCallInner {
     pub arguments: Value,
 }
 
+/// ストリーミング時に分割されて届く tool call の断片  
+/// 同じ `index` を持つ断片を連結することで完全な `FunctionCall` になります  
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct FunctionCallDelta {
+    /// tool_calls 配列内での位置  
+    pub index: usize,
+    /// ツールの呼び出しID  
+    /// 最初の断片にのみ含まれます  
+    pub id: Option<String>,
+    /// ツールの種類:  
+    /// 最初の断片にのみ含まれます  
+    #[serde(rename = "type")]
+    pub tool_type: Option<String>,
+    /// 関数の呼び出し内容の断片  
+    pub function: Option<FunctionCallInnerDelta>,
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct FunctionCallInnerDelta {
+    /// 関数名  
+    /// 最初の断片にのみ含まれます  
+    pub name: Option<String>,
+    /// 関数の引数の断片  
+    /// 連結するとJSON文字列になります  
+    pub arguments: Option<String>,
+}
+
 fn deserialize_arguments<'de, D>(deserializer: D) -> Result<Value, D::Error>
 where
     D: Deserializer<'de>,
@@ -88,7 +152,7 @@ where
             E: de::Error,
         {
             serde_json::from_str(&value)
-                .or_else(|_| Ok(Value::String(value)))
+                .or(Ok(Value::String(value)))
                 .map_err(|e| de::Error::custom::<String>(e))
         }
 
@@ -144,6 +208,7 @@ where
 /// # Example
 ///
 /// ```rust
+/// # use call_agent::chat::function::Tool;
 /// // Assuming MyTool implements the Tool trait:
 /// struct MyTool;
 /// 
@@ -186,4 +251,157 @@ pub trait Tool {
     fn def_parameters(&self) -> serde_json::Value;
     /// 関数の実行  
     fn run(&self, args: serde_json::Value) -> Result<String, String>;
+    /// 関数の実行(画像などを含む結果)  
+    /// Override this to return images or multiple contexts as the tool result.
+    /// By default the output of `run` is wrapped in a single text context.
+    fn run_rich(&self, args: serde_json::Value) -> Result<Vec<MessageContext>, String> {
+        self.run(args).map(|text| vec![MessageContext::Text(text)])
+    }
+}
+
+
+/// 非同期で実行されるtoolの定義  
+/// Async counterpart of the `Tool` trait for tools that perform I/O such as database
+/// queries or HTTP requests.
+///
+/// Async tools are registered with `OpenAIClient::def_async_tool`.
+/// Every type implementing `Tool` also implements `AsyncTool`.
+///
+/// # Example
+///
+/// ```rust
+/// # use call_agent::chat::function::AsyncTool;
+/// struct MyAsyncTool;
+///
+/// #[async_trait::async_trait]
+/// impl AsyncTool for MyAsyncTool {
+///     fn def_name(&self) -> &str {
+///         "my_async_tool"
+///     }
+///
+///     fn def_description(&self) -> &str {
+///         "Fetches data asynchronously."
+///     }
+///
+///     fn def_parameters(&self) -> serde_json::Value {
+///         serde_json::json!({ "type": "object", "properties": {} })
+///     }
+///
+///     async fn run(&self, args: serde_json::Value) -> Result<String, String> {
+///         Ok("fetched".to_string())
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait AsyncTool: Send + Sync {
+    /// 関数名  
+    /// ツール名として使用される  
+    fn def_name(&self) -> &str;
+    /// 関数の説明  
+    fn def_description(&self) -> &str;
+    /// 関数のパラメータの定義(json schema)  
+    fn def_parameters(&self) -> serde_json::Value;
+    /// 関数の非同期実行  
+    async fn run(&self, args: serde_json::Value) -> Result<String, String>;
+    /// 関数の非同期実行(画像などを含む結果)  
+    /// Override this to return images or multiple contexts as the tool result.
+    /// By default the output of `run` is wrapped in a single text context.
+    async fn run_rich(&self, args: serde_json::Value) -> Result<Vec<MessageContext>, String> {
+        self.run(args).await.map(|text| vec![MessageContext::Text(text)])
+    }
+}
+
+#[async_trait]
+impl<T: Tool + Send + Sync> AsyncTool for T {
+    fn def_name(&self) -> &str {
+        Tool::def_name(self)
+    }
+
+    fn def_description(&self) -> &str {
+        Tool::def_description(self)
+    }
+
+    fn def_parameters(&self) -> serde_json::Value {
+        Tool::def_parameters(self)
+    }
+
+    async fn run(&self, args: serde_json::Value) -> Result<String, String> {
+        Tool::run(self, args)
+    }
+
+    async fn run_rich(&self, args: serde_json::Value) -> Result<Vec<MessageContext>, String> {
+        Tool::run_rich(self, args)
+    }
+}
+
+/// Derive `Tool` from a typed argument struct.
+///
+/// # Example
+///
+/// ```
+/// use call_agent::{chat::function::Tool, schemars::JsonSchema};
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize, JsonSchema)]
+/// #[schemars(crate = "call_agent::schemars")]
+/// struct TextLengthArgs {
+///     /// Input text to calculate its length
+///     text: String,
+/// }
+///
+/// /// Returns the length of the input text.
+/// #[derive(Tool)]
+/// #[tool(args = TextLengthArgs)]
+/// struct TextLengthTool;
+///
+/// impl TextLengthTool {
+///     fn call(&self, args: TextLengthArgs) -> Result<String, String> {
+///         Ok(args.text.len().to_string())
+///     }
+/// }
+///
+/// let tool = TextLengthTool;
+/// assert_eq!(Tool::def_name(&tool), "text_length_tool");
+/// assert_eq!(Tool::def_description(&tool), "Returns the length of the input text.");
+/// assert_eq!(Tool::def_parameters(&tool)["required"], serde_json::json!(["text"]));
+/// assert_eq!(Tool::run(&tool, serde_json::json!({ "text": "hello" })), Ok("5".to_string()));
+/// ```
+///
+/// The struct must have a `call` method:
+///
+/// ```compile_fail
+/// use call_agent::{chat::function::Tool, schemars::JsonSchema};
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize, JsonSchema)]
+/// #[schemars(crate = "call_agent::schemars")]
+/// struct TextLengthArgs {
+///     text: String,
+/// }
+///
+/// /// Returns the length of the input text.
+/// #[derive(Tool)]
+/// #[tool(args = TextLengthArgs)]
+/// struct TextLengthTool;
+/// ```
+#[cfg(feature = "derive")]
+pub use call_agent_derive::Tool;
+
+/// Generate the parameter schema of a tool from its argument type.
+///
+/// Sub-schemas are inlined and the `$schema` and `title` keys are removed,
+/// since the API only expects a plain object schema.
+#[cfg(feature = "derive")]
+pub fn tool_parameters<T: schemars::JsonSchema>() -> serde_json::Value {
+    let generator = schemars::generate::SchemaSettings::draft07()
+        .with(|settings| {
+            settings.inline_subschemas = true;
+            settings.meta_schema = None;
+        })
+        .into_generator();
+    let mut schema = generator.into_root_schema_for::<T>().to_value();
+    if let Some(schema) = schema.as_object_mut() {
+        schema.remove("title");
+    }
+    schema
 }
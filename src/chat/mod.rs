@@ -1,6 +1,9 @@
+pub mod anthropic;
 pub mod api;
+pub mod backend;
 pub mod client;
 pub mod function;
 pub mod prompt;
 pub mod err;
+pub mod stream;
 pub mod tokenizer;
\ No newline at end of file
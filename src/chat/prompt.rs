@@ -1,27 +1,39 @@
-use std::fmt;
+use std::{fmt, path::Path, time::{SystemTime, UNIX_EPOCH}};
 
+use base64::prelude::{Engine, BASE64_STANDARD};
 use serde::{ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 
-use super::function::FunctionCall;
+use super::{
+    err::ClientError,
+    function::{FunctionCall, FunctionCallDelta},
+};
 
 /// Represents a prompt message with different roles.
 ///
 /// This enum describes various types of messages used in prompts.
 /// It supports user messages, function messages, and assistant messages.
 /// Each variant holds the content of the message.
+///
+/// Every variant also has `created_at` (Unix time in seconds, set when the message is added
+/// to an `OpenAIClientState`) and a free-form `metadata`. Both are kept locally and in
+/// `export_history`, but never sent to the API.
 #[derive(Clone)]
 pub enum Message {
     /// A message sent by a user.
     /// should the name matches the pattern '^[a-zA-Z0-9_-]+$'."
     User { 
         name: Option<String>,
-        content: Vec<MessageContext> 
+        content: Vec<MessageContext>,
+        created_at: Option<u64>,
+        metadata: Option<Value>,
     },
     /// A message sent by a function, including its name.
     Tool { 
         tool_call_id: String,
-        content: Vec<MessageContext> 
+        content: Vec<MessageContext>,
+        created_at: Option<u64>,
+        metadata: Option<Value>,
     },
     /// A message from the assistant.
     /// should the name matches the pattern '^[a-zA-Z0-9_-]+$'."
@@ -29,51 +41,265 @@ pub enum Message {
         name: Option<String>,
         content: Vec<MessageContext>, 
         tool_calls: Option<Vec<FunctionCall>>,
+        created_at: Option<u64>,
+        metadata: Option<Value>,
     },
     /// A system prompt.
     /// should the name matches the pattern '^[a-zA-Z0-9_-]+$'."
     System { 
         name: Option<String>,
-        content: String
+        content: String,
+        created_at: Option<u64>,
+        metadata: Option<Value>,
     },
     /// A message from the developer.
-    /// Treated as a system message in unsupported models.
+    /// Treated as a system message in unsupported models
+    /// (see `OpenAIClient::set_downgrade_developer_to_system`).
     /// should the name matches the pattern '^[a-zA-Z0-9_-]+$'."
     Developer { 
         name: Option<String>,
-        content: String
+        content: String,
+        created_at: Option<u64>,
+        metadata: Option<Value>,
     },
 }
 
+impl Message {
+    /// Create a user message with a single text content.
+    pub fn user(text: impl Into<String>) -> Self {
+        Message::User {
+            name: None,
+            content: vec![MessageContext::Text(text.into())],
+            created_at: None,
+            metadata: None,
+        }
+    }
+
+    /// Create a named user message with a single text content.
+    pub fn user_named(name: impl Into<String>, text: impl Into<String>) -> Self {
+        Message::User {
+            name: Some(name.into()),
+            content: vec![MessageContext::Text(text.into())],
+            created_at: None,
+            metadata: None,
+        }
+    }
+
+    /// Create a system message.
+    pub fn system(text: impl Into<String>) -> Self {
+        Message::System {
+            name: None,
+            content: text.into(),
+            created_at: None,
+            metadata: None,
+        }
+    }
+
+    /// Create a developer message.
+    pub fn developer(text: impl Into<String>) -> Self {
+        Message::Developer {
+            name: None,
+            content: text.into(),
+            created_at: None,
+            metadata: None,
+        }
+    }
+
+    /// Create an assistant message with a single text content.
+    pub fn assistant(text: impl Into<String>) -> Self {
+        Message::Assistant {
+            name: None,
+            content: vec![MessageContext::Text(text.into())],
+            tool_calls: None,
+            created_at: None,
+            metadata: None,
+        }
+    }
+
+    /// Attach free-form metadata to the message. It is never sent to the API.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use call_agent::chat::prompt::Message;
+    /// let message = Message::user("Hello").with_metadata(serde_json::json!({ "channel": "web" }));
+    /// assert_eq!(message.metadata(), Some(&serde_json::json!({ "channel": "web" })));
+    ///
+    /// // Not part of the request body.
+    /// assert_eq!(serde_json::to_value(&message).unwrap(), serde_json::json!({ "role": "user", "content": "Hello" }));
+    /// ```
+    pub fn with_metadata(mut self, value: Value) -> Self {
+        *self.metadata_mut() = Some(value);
+        self
+    }
+
+    /// The free-form metadata of the message, if set.
+    pub fn metadata(&self) -> Option<&Value> {
+        match self {
+            Message::User { metadata, .. }
+            | Message::Tool { metadata, .. }
+            | Message::Assistant { metadata, .. }
+            | Message::System { metadata, .. }
+            | Message::Developer { metadata, .. } => metadata.as_ref(),
+        }
+    }
+
+    /// Mutable access to the free-form metadata of the message.
+    pub fn metadata_mut(&mut self) -> &mut Option<Value> {
+        match self {
+            Message::User { metadata, .. }
+            | Message::Tool { metadata, .. }
+            | Message::Assistant { metadata, .. }
+            | Message::System { metadata, .. }
+            | Message::Developer { metadata, .. } => metadata,
+        }
+    }
+
+    /// Unix time (seconds) the message was added to the prompt, if known.
+    pub fn created_at(&self) -> Option<u64> {
+        match self {
+            Message::User { created_at, .. }
+            | Message::Tool { created_at, .. }
+            | Message::Assistant { created_at, .. }
+            | Message::System { created_at, .. }
+            | Message::Developer { created_at, .. } => *created_at,
+        }
+    }
+
+    /// Set `created_at` to the current time unless it is already set.
+    pub(crate) fn stamp(&mut self) {
+        let (Message::User { created_at, .. }
+        | Message::Tool { created_at, .. }
+        | Message::Assistant { created_at, .. }
+        | Message::System { created_at, .. }
+        | Message::Developer { created_at, .. }) = self;
+        if created_at.is_none() {
+            *created_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|elapsed| elapsed.as_secs());
+        }
+    }
+
+    /// Serialize the message with its local fields (`created_at`, `metadata`), for saving a transcript.
+    pub(crate) fn to_transcript(&self) -> Result<Value, serde_json::Error> {
+        let mut value = serde_json::to_value(self)?;
+        if let Some(object) = value.as_object_mut() {
+            if let Some(created_at) = self.created_at() {
+                object.insert("created_at".to_string(), created_at.into());
+            }
+            if let Some(metadata) = self.metadata() {
+                object.insert("metadata".to_string(), metadata.clone());
+            }
+        }
+        Ok(value)
+    }
+
+    /// The name of the message sender, if set.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Message::User { name, .. }
+            | Message::Assistant { name, .. }
+            | Message::System { name, .. }
+            | Message::Developer { name, .. } => name.as_deref(),
+            Message::Tool { .. } => None,
+        }
+    }
+
+    /// Check that the name matches the pattern `^[a-zA-Z0-9_-]+$` required by the API.
+    ///
+    /// # Returns
+    ///
+    /// `ClientError::InvalidInput` naming the invalid value.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use call_agent::chat::prompt::Message;
+    /// assert!(Message::user_named("alice_01", "Hi").validate().is_ok());
+    /// assert!(Message::user_named("John Doe", "Hi").validate().is_err());
+    /// assert!(Message::user_named("太郎", "Hi").validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<(), ClientError> {
+        match self.name() {
+            Some(name) if !is_valid_name(name) => Err(ClientError::InvalidInput(format!(
+                "name must match ^[a-zA-Z0-9_-]+$, got {:?}",
+                name
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replace the characters of the name that the API does not accept with `_`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use call_agent::chat::prompt::Message;
+    /// let mut message = Message::user_named("John Doe", "Hi");
+    /// message.sanitize_name();
+    /// assert_eq!(message.name(), Some("John_Doe"));
+    /// ```
+    pub fn sanitize_name(&mut self) {
+        if let Message::User { name: Some(name), .. }
+        | Message::Assistant { name: Some(name), .. }
+        | Message::System { name: Some(name), .. }
+        | Message::Developer { name: Some(name), .. } = self
+        {
+            if !is_valid_name(name) {
+                *name = sanitize_name(name);
+            }
+        }
+    }
+}
+
+/// Whether the name matches the pattern `^[a-zA-Z0-9_-]+$`.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Replace the characters not matching `[a-zA-Z0-9_-]` with `_`.
+pub fn sanitize_name(name: &str) -> String {
+    if name.is_empty() {
+        return "_".to_string();
+    }
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
+}
+
 impl fmt::Debug for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Message::User { name, content } => {
+            Message::User { name, content, .. } => {
                 writeln!(f, "User: {}", name.as_deref().unwrap_or("Anonymous"))?;
                 for ctx in content {
                     match ctx {
                         MessageContext::Text(text) => writeln!(f, "    {}", text)?,
                         MessageContext::Image(image) => writeln!(f, "    [Image URL: {}]", image.url)?,
+                        MessageContext::Audio(audio) => writeln!(f, "    [Audio: {}]", audio.format)?,
                     }
                 }
                 Ok(())
             }
-            Message::Tool { tool_call_id, content } => {
+            Message::Tool { tool_call_id, content, .. } => {
                 writeln!(f, "Tool: {} - Tool Call", tool_call_id)?;
                 for ctx in content {
                     match ctx {
                         MessageContext::Text(text) => writeln!(f, "    {}", text)?,
                         MessageContext::Image(image) => writeln!(f, "    [Image URL: {}]", image.url)?,
+                        MessageContext::Audio(audio) => writeln!(f, "    [Audio: {}]", audio.format)?,
                     }
                 }
                 Ok(())
             }
-            Message::Assistant { name, content, tool_calls } => {
+            Message::Assistant { name, content, tool_calls, .. } => {
                 writeln!(f, "Assistant: {}", name.as_deref().unwrap_or("Assistant"))?;
                 for ctx in content {
                     match ctx {
                         MessageContext::Text(text) => writeln!(f, "    {}", text)?,
                         MessageContext::Image(image) => writeln!(f, "    [Image URL: {}]", image.url)?,
+                        MessageContext::Audio(audio) => writeln!(f, "    [Audio: {}]", audio.format)?,
                     }
                 }
                 if let Some(calls) = tool_calls {
@@ -83,11 +309,11 @@ impl fmt::Debug for Message {
                 }
                 Ok(())
             }
-            Message::System { name, content } => {
+            Message::System { name, content, .. } => {
                 writeln!(f, "System: {}", name.as_deref().unwrap_or("System"))?;
                 writeln!(f, "    {}", content)
             }
-            Message::Developer { name, content } => {
+            Message::Developer { name, content, .. } => {
                 writeln!(f, "Developer: {}", name.as_deref().unwrap_or("Developer"))?;
                 writeln!(f, "    {}", content)
             }
@@ -102,7 +328,7 @@ impl Serialize for Message {
         S: Serializer,
     {
         let state = match self {
-            Message::User { name, content } => {
+            Message::User { name, content, .. } => {
                 let mut s = serializer.serialize_struct("Message", 3)?;
                 s.serialize_field("role", "user")?;
                 if let Some(name) = name {
@@ -111,7 +337,7 @@ impl Serialize for Message {
                 serialize_content_field(&mut s, content)?;
                 s
             }
-            Message::Tool { tool_call_id, content } => {
+            Message::Tool { tool_call_id, content, .. } => {
                 let mut s = serializer.serialize_struct("Message", 2)?;
                 s.serialize_field("role", "tool")?;
                 s.serialize_field("tool_call_id", tool_call_id)?;
@@ -119,7 +345,7 @@ impl Serialize for Message {
                 serialize_content_field(&mut s, content)?;
                 s
             }
-            Message::Assistant { name, content, tool_calls } => {
+            Message::Assistant { name, content, tool_calls, .. } => {
                 let mut s = serializer.serialize_struct("Message", 3)?;
                 s.serialize_field("role", "assistant")?;
                 if let Some(name) = name {
@@ -131,7 +357,7 @@ impl Serialize for Message {
                 }
                 s
             }
-            Message::System { name, content } => {
+            Message::System { name, content, .. } => {
                 let mut s = serializer.serialize_struct("Message", 3)?;
                 s.serialize_field("role", "system")?;
                 if let Some(name) = name {
@@ -140,7 +366,7 @@ impl Serialize for Message {
                 s.serialize_field("content", content)?;
                 s
             }
-            Message::Developer { name, content } => {
+            Message::Developer { name, content, .. } => {
                 let mut s = serializer.serialize_struct("Message", 3)?;
                 s.serialize_field("role", "developer")?;
                 if let Some(name) = name {
@@ -177,6 +403,21 @@ where
     Ok(())
 }
 
+/// Helper function for deserializing the "content" field of a message.
+///
+/// Accepts a plain string as written by `serialize_content_field`, an array of contexts,
+/// or a missing/null content.
+fn deserialize_content_field<E>(value: &Value) -> Result<Vec<MessageContext>, E>
+where
+    E: serde::de::Error,
+{
+    match value.get("content") {
+        None | Some(Value::Null) => Ok(vec![]),
+        Some(Value::String(text)) => Ok(vec![MessageContext::Text(text.clone())]),
+        Some(content) => serde_json::from_value(content.clone()).map_err(E::custom),
+    }
+}
+
 // Custom deserialization implementation for Message.
 impl<'de> Deserialize<'de> for Message {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -186,15 +427,15 @@ impl<'de> Deserialize<'de> for Message {
         let value: Value = Deserialize::deserialize(deserializer)?;
 
         let role = value.get("role").and_then(Value::as_str).unwrap_or("");
+        // Local fields, present in histories saved with `export_history`.
+        let created_at = value.get("created_at").and_then(Value::as_u64);
+        let metadata = value.get("metadata").filter(|metadata| !metadata.is_null()).cloned();
 
         match role {
             "user" => {
             let name = value.get("name").and_then(Value::as_str).map(String::from);
-            let content = serde_json::from_value(
-                value.get("content").cloned().unwrap_or_default(),
-            )
-            .map_err(serde::de::Error::custom)?;
-            Ok(Message::User { name, content })
+            let content = deserialize_content_field(&value)?;
+            Ok(Message::User { name, content, created_at, metadata })
             }
             "tool" => {
             let tool_call_id = value
@@ -202,22 +443,16 @@ impl<'de> Deserialize<'de> for Message {
                 .and_then(Value::as_str)
                 .ok_or_else(|| serde::de::Error::missing_field("tool_call_id"))?
                 .to_string();
-            let content = serde_json::from_value(
-                value.get("content").cloned().unwrap_or_default(),
-            )
-            .map_err(serde::de::Error::custom)?;
-            Ok(Message::Tool { tool_call_id, content })
+            let content = deserialize_content_field(&value)?;
+            Ok(Message::Tool { tool_call_id, content, created_at, metadata })
             }
             "assistant" => {
                 let name = value.get("name").and_then(Value::as_str).map(String::from);
-                let content = serde_json::from_value(
-                    value.get("content").cloned().unwrap_or_default(),
-                )
-                .map_err(serde::de::Error::custom)?;
+                let content = deserialize_content_field(&value)?;
                 let tool_calls = value.get("tool_calls").map_or(Ok(None), |v| {
                     serde_json::from_value(v.clone()).map(Some)
                 }).map_err(serde::de::Error::custom)?;
-                Ok(Message::Assistant { name, content, tool_calls })
+                Ok(Message::Assistant { name, content, tool_calls, created_at, metadata })
             }
             "system" => {
                 let name = value.get("name").and_then(Value::as_str).map(String::from);
@@ -226,7 +461,7 @@ impl<'de> Deserialize<'de> for Message {
                     .and_then(Value::as_str)
                     .ok_or_else(|| serde::de::Error::missing_field("content"))?
                     .to_string();
-                Ok(Message::System { name, content })
+                Ok(Message::System { name, content, created_at, metadata })
             }
             "developer" => {
                 let name = value.get("name").and_then(Value::as_str).map(String::from);
@@ -235,7 +470,7 @@ impl<'de> Deserialize<'de> for Message {
                     .and_then(Value::as_str)
                     .ok_or_else(|| serde::de::Error::missing_field("content"))?
                     .to_string();
-                Ok(Message::Developer { name, content })
+                Ok(Message::Developer { name, content, created_at, metadata })
             }
             _ => Err(serde::de::Error::custom("Invalid message type")),
         }
@@ -244,13 +479,42 @@ impl<'de> Deserialize<'de> for Message {
 
 /// Represents a context within a message.
 ///
-/// This enum supports either textual content or image content.
-#[derive(Debug, Deserialize, Clone)]
+/// This enum supports textual, image or audio content.
+#[derive(Debug, Clone)]
 pub enum MessageContext {
     /// A text message context.
     Text(String),
     /// An image message context.
     Image(MessageImage),
+    /// An audio message context.
+    Audio(MessageAudio),
+}
+
+impl MessageContext {
+    /// Load a local image file as an image context.
+    ///
+    /// Same as `MessageImage::from_path`, except that a missing file is reported as `NotFound`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the image file.
+    /// * `detail` - The resolution detail of the image (e.g. "low", "auto").
+    ///
+    /// # Returns
+    ///
+    /// The image context, `ClientError::NotFound` if the file does not exist,
+    /// or `ClientError::InvalidInput` if the extension is not supported.
+    pub fn from_image_path(path: impl AsRef<Path>, detail: Option<String>) -> Result<MessageContext, ClientError> {
+        let path = path.as_ref();
+        MessageImage::from_path(path, detail)
+            .map(MessageContext::Image)
+            .map_err(|e| match e {
+                ClientError::IoError(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    ClientError::NotFound(path.display().to_string())
+                }
+                e => e,
+            })
+    }
 }
 
 // Custom serialization implementation for MessageContext.
@@ -272,6 +536,50 @@ impl Serialize for MessageContext {
                 state.serialize_field("image_url", image)?;
                 state.end()
             }
+            MessageContext::Audio(audio) => {
+                let mut state = serializer.serialize_struct("MessageContext", 2)?;
+                state.serialize_field("type", "input_audio")?;
+                state.serialize_field("input_audio", audio)?;
+                state.end()
+            }
+        }
+    }
+}
+
+// Custom deserialization implementation for MessageContext.
+impl<'de> Deserialize<'de> for MessageContext {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value: Value = Deserialize::deserialize(deserializer)?;
+
+        match value.get("type").and_then(Value::as_str).unwrap_or("") {
+            "text" => {
+                let text = value
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| serde::de::Error::missing_field("text"))?
+                    .to_string();
+                Ok(MessageContext::Text(text))
+            }
+            "image_url" => {
+                let image = value
+                    .get("image_url")
+                    .cloned()
+                    .ok_or_else(|| serde::de::Error::missing_field("image_url"))?;
+                let image = serde_json::from_value(image).map_err(serde::de::Error::custom)?;
+                Ok(MessageContext::Image(image))
+            }
+            "input_audio" => {
+                let audio = value
+                    .get("input_audio")
+                    .cloned()
+                    .ok_or_else(|| serde::de::Error::missing_field("input_audio"))?;
+                let audio = serde_json::from_value(audio).map_err(serde::de::Error::custom)?;
+                Ok(MessageContext::Audio(audio))
+            }
+            _ => Err(serde::de::Error::custom("Invalid message context type")),
         }
     }
 }
@@ -298,6 +606,75 @@ pub struct MessageImage {
     pub detail: Option<String>,
 }
 
+impl MessageImage {
+    /// Create an image from base64-encoded data.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The base64-encoded image.
+    /// * `mime` - The MIME type of the image; e.g. "image/png".
+    pub fn from_base64(data: &str, mime: &str) -> Self {
+        Self {
+            url: format!("data:{};base64,{}", mime, data),
+            detail: None,
+        }
+    }
+
+    /// Load a local image file.
+    ///
+    /// The MIME type is inferred from the extension (png, jpg/jpeg, webp or gif),
+    /// and the file is embedded as a base64 data URI.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the image file.
+    /// * `detail` - The resolution detail of the image (e.g. "low", "auto").
+    ///
+    /// # Returns
+    ///
+    /// The image, `ClientError::IoError` if the file cannot be read,
+    /// or `ClientError::InvalidInput` if the extension is not supported.
+    pub fn from_path(path: impl AsRef<Path>, detail: Option<String>) -> Result<MessageImage, ClientError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        let mime = match extension.as_deref() {
+            Some("png") => "image/png",
+            Some("jpg") | Some("jpeg") => "image/jpeg",
+            Some("webp") => "image/webp",
+            Some("gif") => "image/gif",
+            _ => {
+                return Err(ClientError::InvalidInput(format!(
+                    "unsupported image extension: {}",
+                    path.display()
+                )))
+            }
+        };
+        let data = std::fs::read(path)?;
+        let mut image = MessageImage::from_base64(&BASE64_STANDARD.encode(data), mime);
+        image.detail = detail;
+        Ok(image)
+    }
+}
+
+/// Represents an audio clip used within a message.
+///
+/// Requires a model that accepts audio input.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MessageAudio {
+    /// The base64-encoded audio data.
+    pub data: String,
+
+    /// The format of the audio data.
+    ///
+    /// For example, for OpenAI API, valid values are:
+    /// - "wav"
+    /// - "mp3"
+    pub format: String,
+}
+
 /// Represents a choice from the API response.
 ///
 /// A choice contains a response message and a finish reason.
@@ -309,8 +686,113 @@ pub struct Choice {
     /// The message associated with this choice.
     pub message: ResponseMessage,
 
-    /// The reason for finishing, as a string.
-    pub finish_reason: String,
+    /// The reason the model stopped generating.
+    pub finish_reason: FinishReason,
+
+    /// Log probabilities of the output tokens (if requested).
+    #[serde(default)]
+    pub logprobs: Option<ChoiceLogprobs>,
+}
+
+/// Represents the log probability information of a choice.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ChoiceLogprobs {
+    /// Log probabilities of the content tokens.
+    pub content: Option<Vec<TokenLogprob>>,
+
+    /// Log probabilities of the refusal tokens.
+    #[serde(default)]
+    pub refusal: Option<Vec<TokenLogprob>>,
+}
+
+/// Represents the log probability of a single output token.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TokenLogprob {
+    /// The token.
+    pub token: String,
+
+    /// The log probability of the token.
+    pub logprob: f64,
+
+    /// The UTF-8 bytes of the token (if any).
+    pub bytes: Option<Vec<u8>>,
+
+    /// The most likely alternative tokens at this position.
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// Represents an alternative token and its log probability.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TopLogprob {
+    /// The token.
+    pub token: String,
+
+    /// The log probability of the token.
+    pub logprob: f64,
+
+    /// The UTF-8 bytes of the token (if any).
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// Represents the reason why the model stopped generating tokens.
+///
+/// Unknown values returned by the API are kept as `Other` instead of failing deserialization.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinishReason {
+    /// The model reached a natural stop point or a stop sequence.
+    Stop,
+    /// The output was cut off by the maximum token limit.
+    Length,
+    /// The model called one or more tools.
+    ToolCalls,
+    /// Content was omitted due to a content filter.
+    ContentFilter,
+    /// Any other reason not covered above.
+    Other(String),
+}
+
+impl FinishReason {
+    /// Returns the finish reason as the raw string used by the API.
+    pub fn as_str(&self) -> &str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::Length => "length",
+            FinishReason::ToolCalls => "tool_calls",
+            FinishReason::ContentFilter => "content_filter",
+            FinishReason::Other(reason) => reason,
+        }
+    }
+}
+
+impl From<&str> for FinishReason {
+    fn from(reason: &str) -> Self {
+        match reason {
+            "stop" => FinishReason::Stop,
+            "length" => FinishReason::Length,
+            "tool_calls" => FinishReason::ToolCalls,
+            "content_filter" => FinishReason::ContentFilter,
+            other => FinishReason::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+// Custom deserialization implementation for FinishReason.
+// A null value is treated as `Other("")` rather than an error.
+impl<'de> Deserialize<'de> for FinishReason {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let reason: Option<String> = Deserialize::deserialize(deserializer)?;
+        Ok(FinishReason::from(reason.as_deref().unwrap_or("")))
+    }
 }
 
 /// Represents a response message from the API.
@@ -332,5 +814,103 @@ pub struct ResponseMessage {
 
     /// annotation for web search options
     #[serde(default)]
-    pub annotations: Option<serde_json::Value>
-}
\ No newline at end of file
+    pub annotations: Option<Vec<Annotation>>
+}
+
+/// An annotation of a response message.
+///
+/// An annotation that cannot be read as a known type becomes `Other`, so it
+/// never fails the whole response.
+///
+/// ```
+/// use call_agent::chat::prompt::Annotation;
+///
+/// let annotation: Annotation = serde_json::from_value(serde_json::json!({
+///     "type": "url_citation",
+///     "url_citation": { "url": "https://example.com", "title": "Example" }
+/// })).unwrap();
+/// assert!(matches!(annotation, Annotation::UrlCitation { url_citation } if url_citation.start_index == 0));
+///
+/// let annotation: Annotation = serde_json::from_value(serde_json::json!({
+///     "type": "url_citation",
+///     "url_citation": { "title": "No URL" }
+/// })).unwrap();
+/// assert!(matches!(annotation, Annotation::Other));
+/// ```
+#[derive(Debug, Clone)]
+pub enum Annotation {
+    /// A citation of a web page used by web search.
+    UrlCitation {
+        url_citation: UrlCitation,
+    },
+    /// An annotation this crate does not know or cannot read.
+    Other,
+}
+
+impl<'de> Deserialize<'de> for Annotation {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+        let annotation = match value["type"].as_str() {
+            Some("url_citation") => match UrlCitation::deserialize(&value["url_citation"]) {
+                Ok(url_citation) => Annotation::UrlCitation { url_citation },
+                Err(_) => Annotation::Other,
+            },
+            _ => Annotation::Other,
+        };
+        Ok(annotation)
+    }
+}
+
+/// A citation of a web page.
+#[derive(Debug, Deserialize, Clone)]
+pub struct UrlCitation {
+    /// The URL of the web page.
+    pub url: String,
+    /// The title of the web page.
+    #[serde(default)]
+    pub title: String,
+    /// Index of the first character of the citation in the message content.
+    ///
+    /// `0` when the API does not send it.
+    #[serde(default)]
+    pub start_index: usize,
+    /// Index after the last character of the citation in the message content.
+    ///
+    /// `0` when the API does not send it.
+    #[serde(default)]
+    pub end_index: usize,
+}
+
+/// Represents a choice from a streaming API response chunk.
+#[derive(Debug, Deserialize, Clone)]
+pub struct StreamChoice {
+    /// The index of the choice in the response.
+    pub index: usize,
+
+    /// The incremental message fragment for this choice.
+    pub delta: ChatDelta,
+
+    /// The reason for finishing, present only on the last chunk of the choice.
+    pub finish_reason: Option<FinishReason>,
+}
+
+/// Represents an incremental message fragment received while streaming.
+///
+/// Concatenating the fragments in order yields the complete assistant message.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ChatDelta {
+    /// The role of the message sender, present only on the first chunk.
+    pub role: Option<String>,
+
+    /// A fragment of the text content.
+    pub content: Option<String>,
+
+    /// Fragments of tool calls, identified by their index.
+    pub tool_calls: Option<Vec<FunctionCallDelta>>,
+
+    /// A fragment of the refusal message.
+    pub refusal: Option<String>,
+}
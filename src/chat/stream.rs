@@ -0,0 +1,382 @@
+use std::{collections::VecDeque, future::Future, pin::Pin, task::{Context, Poll}};
+
+use futures::{Stream, StreamExt};
+use reqwest::Response;
+use serde_json::Value;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+
+use super::{
+    api::{APIStreamResponse, APIUsage},
+    client::OpenAIClientState,
+    err::ClientError,
+    function::{FunctionCall, FunctionCallDelta, FunctionCallInner},
+    prompt::{ChatDelta, FinishReason, Message, MessageContext, StreamChoice},
+};
+
+type ByteStream = Pin<Box<dyn Stream<Item = reqwest::Result<Vec<u8>>> + Send>>;
+
+/// Stream of incremental chunks returned by `OpenAIClientState::generate_stream`.
+///
+/// Implements `futures::Stream<Item = Result<ChatDelta, ClientError>>`.
+/// When the server finishes the stream and every delta has been yielded, the assembled
+/// assistant message is added to the prompt of the originating state. The same happens
+/// with the text yielded so far when the stream is cancelled; partial tool calls are
+/// dropped then. If the connection closes before the response is complete, the stream
+/// yields `ClientError::NetworkError` and the prompt is left unchanged.
+///
+/// A message with tool calls is only added together with the results of those calls,
+/// since the API rejects tool calls without a response: run them with `run_tool_calls`
+/// once the stream has finished. Otherwise the prompt is left unchanged.
+pub struct ChatStream<'a> {
+    state: &'a mut OpenAIClientState,
+    model_name: Option<String>,
+    inner: ByteStream,
+    /// Resolves when the generation is cancelled.
+    cancel: Option<Pin<Box<WaitForCancellationFutureOwned>>>,
+    /// Bytes received but not yet terminated by a newline.
+    buffer: Vec<u8>,
+    /// Parsed items waiting to be yielded.
+    pending: VecDeque<Result<StreamChoice, ClientError>>,
+    /// Text content yielded so far.
+    content: String,
+    /// Tool calls yielded so far, ordered by their index.
+    tool_calls: Vec<PartialToolCall>,
+    finish_reason: Option<FinishReason>,
+    /// Token usage, sent in the last chunk when requested with `stream_options`.
+    usage: Option<APIUsage>,
+    /// Assistant message with tool calls waiting for `run_tool_calls`.
+    unresolved: Option<Message>,
+    /// Whether the server sent a finish reason or `[DONE]`.
+    done: bool,
+    /// Whether the response was received completely and added to the prompt.
+    completed: bool,
+    /// Whether no more data is read from the response.
+    finished: bool,
+}
+
+/// A tool call whose fragments are still being concatenated.
+#[derive(Default)]
+struct PartialToolCall {
+    id: String,
+    tool_type: String,
+    name: String,
+    arguments: String,
+}
+
+impl<'a> ChatStream<'a> {
+    pub(crate) fn new(state: &'a mut OpenAIClientState, model_name: Option<String>, res: Response, cancel: Option<CancellationToken>) -> Self {
+        Self {
+            state,
+            model_name,
+            inner: Box::pin(res.bytes_stream().map(|chunk| chunk.map(|bytes| bytes.to_vec()))),
+            cancel: cancel.map(|cancel| Box::pin(cancel.cancelled_owned())),
+            buffer: Vec::new(),
+            pending: VecDeque::new(),
+            content: String::new(),
+            tool_calls: Vec::new(),
+            finish_reason: None,
+            usage: None,
+            unresolved: None,
+            done: false,
+            completed: false,
+            finished: false,
+        }
+    }
+
+    /// Retrieve the assistant message collected so far.
+    ///
+    /// This is useful when the stream fails midway, since the partial message
+    /// is not added to the prompt in that case.
+    ///
+    /// # Returns
+    ///
+    /// The partial assistant message, or None if nothing has been received yet.
+    pub fn partial_message(&self) -> Option<Message> {
+        if self.content.is_empty() && self.tool_calls.is_empty() {
+            return None;
+        }
+
+        let content = if self.content.is_empty() {
+            vec![]
+        } else {
+            vec![MessageContext::Text(self.content.clone())]
+        };
+        let tool_calls = if self.tool_calls.is_empty() {
+            None
+        } else {
+            Some(self.tool_calls.iter().map(PartialToolCall::to_function_call).collect())
+        };
+
+        Some(Message::Assistant {
+            name: self.model_name.clone(),
+            content,
+            tool_calls,
+            created_at: None,
+            metadata: None,
+        })
+    }
+
+    /// Retrieve the tool calls assembled from the fragments yielded so far.
+    ///
+    /// The argument fragments are concatenated by the index of each call, so once the
+    /// stream has returned `None` these are the complete tool calls, ready to be run.
+    /// Arguments that are not (yet) valid JSON are kept as a string.
+    /// Empty once the stream has been cancelled.
+    pub fn tool_calls(&self) -> Vec<FunctionCall> {
+        self.tool_calls.iter().map(PartialToolCall::to_function_call).collect()
+    }
+
+    /// The tool calls of the completed response.
+    ///
+    /// Unlike `tool_calls`, this is only set once the whole response has been received,
+    /// so the arguments are complete and the calls can be run with `run_tool_calls`.
+    ///
+    /// # Returns
+    ///
+    /// The tool calls, or None while the stream is running, when it failed or was cancelled,
+    /// or when the model did not call any tool.
+    pub fn finished_tool_calls(&self) -> Option<Vec<FunctionCall>> {
+        if self.completed && !self.tool_calls.is_empty() {
+            Some(self.tool_calls())
+        } else {
+            None
+        }
+    }
+
+    /// Whether the stream has finished, either normally, with an error or by cancellation.
+    pub fn is_finished(&self) -> bool {
+        self.finished && self.pending.is_empty()
+    }
+
+    /// The reason the model stopped generating, once it has been received.
+    pub fn finish_reason(&self) -> Option<&FinishReason> {
+        self.finish_reason.as_ref()
+    }
+
+    /// The token usage of the request, once it has been received.
+    ///
+    /// Only sent by the API when `ModelConfig::stream_options` sets `include_usage`,
+    /// in a chunk without choices right before the end of the stream.
+    pub fn usage(&self) -> Option<&APIUsage> {
+        self.usage.as_ref()
+    }
+
+    /// Run the tool calls of the finished stream and add them to the prompt.
+    ///
+    /// The tools are run the same way `OpenAIClientState::generate_can_use_tool` runs them,
+    /// then the assistant message and the tool results are added to the prompt.
+    /// If a tool is not found or disabled, nothing is added and the call can be retried.
+    ///
+    /// # Arguments
+    ///
+    /// * `show_call` - Optional callback function to show the tool call.(eg, `show_call("tool_name", "args")`)
+    ///
+    /// # Returns
+    ///
+    /// The tool messages, empty if the stream has not finished with tool calls, or a ClientError.
+    pub async fn run_tool_calls<F>(&mut self, show_call: Option<&F>) -> Result<Vec<Message>, ClientError>
+    where F: Fn(&str, &Value) {
+        let Some(message) = self.unresolved.take() else {
+            return Ok(vec![]);
+        };
+        let calls = match &message {
+            Message::Assistant { tool_calls: Some(calls), .. } => calls.clone(),
+            _ => vec![],
+        };
+        let results = match self.state.client.run_tool_calls(&calls, show_call).await {
+            Ok(results) => results,
+            Err(e) => {
+                self.unresolved = Some(message);
+                return Err(e);
+            }
+        };
+        let mut messages = vec![message];
+        messages.extend(results.iter().cloned());
+        self.state.push(messages);
+        Ok(results)
+    }
+
+    /// Split the buffered bytes into lines and handle each complete one.
+    fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            self.handle_line(line.trim_end());
+        }
+    }
+
+    /// Handle a single server-sent event line.
+    fn handle_line(&mut self, line: &str) {
+        if self.finished {
+            return;
+        }
+        // Ignore comments, event names and blank separators.
+        let data = match line.strip_prefix("data:") {
+            Some(data) => data.trim(),
+            None => return,
+        };
+        if data == "[DONE]" {
+            self.done = true;
+            self.finished = true;
+            return;
+        }
+
+        log::debug!("Stream chunk: {}", data);
+        match serde_json::from_str::<APIStreamResponse>(data) {
+            Ok(APIStreamResponse { error: Some(err), .. }) => self.fail(err.into()),
+            Ok(chunk) => {
+                // Some servers repeat the usage on every chunk, so only the last one is kept.
+                if let Some(usage) = chunk.usage {
+                    self.usage = Some(usage);
+                }
+                for choice in chunk.choices.unwrap_or_default() {
+                    // `generate_stream` rejects `n > 1`, so any other choice is not part of the reply.
+                    if choice.index != 0 {
+                        continue;
+                    }
+                    if choice.finish_reason.is_some() {
+                        self.done = true;
+                    }
+                    self.pending.push_back(Ok(choice));
+                }
+            }
+            Err(_) => self.fail(ClientError::InvalidResponse),
+        }
+    }
+
+    /// Stop reading the response and yield `err` after the items already parsed.
+    fn fail(&mut self, err: ClientError) {
+        self.done = false;
+        self.finished = true;
+        self.pending.push_back(Err(err));
+    }
+
+    /// Merge a choice that is about to be yielded into the collected message.
+    fn accumulate(&mut self, choice: &StreamChoice) {
+        if let Some(reason) = &choice.finish_reason {
+            self.finish_reason = Some(reason.clone());
+        }
+        if let Some(content) = &choice.delta.content {
+            self.content.push_str(content);
+        }
+        for call in choice.delta.tool_calls.iter().flatten() {
+            self.accumulate_tool_call(call);
+        }
+    }
+
+    fn accumulate_tool_call(&mut self, delta: &FunctionCallDelta) {
+        if self.tool_calls.len() <= delta.index {
+            self.tool_calls.resize_with(delta.index + 1, PartialToolCall::default);
+        }
+        let call = &mut self.tool_calls[delta.index];
+        if let Some(id) = &delta.id {
+            call.id.push_str(id);
+        }
+        if let Some(tool_type) = &delta.tool_type {
+            call.tool_type.push_str(tool_type);
+        }
+        if let Some(function) = &delta.function {
+            if let Some(name) = &function.name {
+                call.name.push_str(name);
+            }
+            if let Some(arguments) = &function.arguments {
+                call.arguments.push_str(arguments);
+            }
+        }
+    }
+
+    /// Mark the response as complete and add the assembled message to the prompt.
+    fn finish(&mut self) {
+        self.completed = true;
+        self.add_message();
+    }
+
+    /// Finish a cancelled stream, adding only the text yielded so far to the prompt.
+    ///
+    /// Partial tool calls cannot be run, so they are dropped.
+    fn cancel_finish(&mut self) {
+        self.finished = true;
+        self.tool_calls.clear();
+        self.add_message();
+    }
+
+    /// Add the assembled message and the usage to the state.
+    ///
+    /// A message with tool calls is kept back until `run_tool_calls` is called.
+    fn add_message(&mut self) {
+        if let Some(usage) = &self.usage {
+            self.state.usage_total.add(usage);
+        }
+        match self.partial_message() {
+            Some(message) if self.tool_calls.is_empty() => self.state.push(vec![message]),
+            Some(message) => self.unresolved = Some(message),
+            None => {}
+        }
+    }
+}
+
+impl PartialToolCall {
+    fn to_function_call(&self) -> FunctionCall {
+        FunctionCall {
+            id: self.id.clone(),
+            tool_type: if self.tool_type.is_empty() { "function".to_string() } else { self.tool_type.clone() },
+            function: FunctionCallInner {
+                name: self.name.clone(),
+                // Same as the non-streaming path: fall back to a plain string if it is not valid JSON.
+                arguments: serde_json::from_str(&self.arguments)
+                    .unwrap_or_else(|_| Value::String(self.arguments.clone())),
+            },
+        }
+    }
+}
+
+impl Stream for ChatStream<'_> {
+    type Item = Result<ChatDelta, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if let Some(cancel) = this.cancel.as_mut().filter(|_| !this.finished) {
+                if cancel.as_mut().poll(cx).is_ready() {
+                    // Drop the connection and keep what has been yielded so far.
+                    this.cancel = None;
+                    this.inner = Box::pin(futures::stream::empty());
+                    // Deltas that were not yielded are dropped, so the prompt only holds what the caller saw.
+                    this.pending.clear();
+                    this.cancel_finish();
+                    return Poll::Ready(Some(Err(ClientError::Cancelled)));
+                }
+            }
+            if let Some(item) = this.pending.pop_front() {
+                return Poll::Ready(Some(item.map(|choice| {
+                    this.accumulate(&choice);
+                    choice.delta
+                })));
+            }
+            if this.finished {
+                if this.done && !this.completed {
+                    this.finish();
+                }
+                return Poll::Ready(None);
+            }
+            match this.inner.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(bytes))) => this.feed(&bytes),
+                Poll::Ready(Some(Err(e))) => this.fail(ClientError::from_reqwest(e)),
+                Poll::Ready(None) => {
+                    // Handle a trailing line without a newline, then close the stream.
+                    this.feed(b"\n");
+                    if !this.finished {
+                        // Closed without a finish reason or `[DONE]`, so the reply may be cut off.
+                        if this.done {
+                            this.finished = true;
+                        } else {
+                            this.fail(ClientError::NetworkError);
+                        }
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
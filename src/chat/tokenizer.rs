@@ -0,0 +1,110 @@
+use super::prompt::{Message, MessageContext};
+
+/// Tokens added for every message by the chat format (role and separators).
+const TOKENS_PER_MESSAGE: usize = 3;
+/// Tokens added for the name of a message.
+const TOKENS_PER_NAME: usize = 1;
+/// Tokens priming the assistant reply.
+const TOKENS_PER_REPLY: usize = 3;
+/// Tokens of an image with `detail: "low"`.
+const LOW_DETAIL_IMAGE_TOKENS: usize = 85;
+/// Tokens of an image with high or automatic detail, assuming a 1024x1024 image.
+const HIGH_DETAIL_IMAGE_TOKENS: usize = 765;
+/// Bytes of audio per token, assuming 128 kbps audio and about 10 tokens per second.
+const AUDIO_BYTES_PER_TOKEN: usize = 1600;
+
+/// Estimate the number of tokens of a text.
+///
+/// With the `tokens` feature, the text is encoded with the `o200k_base` BPE used by the GPT-4o family.
+#[cfg(feature = "tokens")]
+pub fn estimate_text_tokens(text: &str) -> usize {
+    tiktoken_rs::o200k_base_singleton().encode_ordinary(text).len()
+}
+
+/// Count the tokens of a text with the BPE of the given model.
+///
+/// Models the tokenizer does not know (e.g. newer or non-OpenAI models) use `o200k_base`.
+#[cfg(feature = "tokens")]
+pub fn count_text_tokens(text: &str, model: &str) -> usize {
+    use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer};
+    let bpe = match get_tokenizer(model) {
+        Some(Tokenizer::O200kHarmony) => tiktoken_rs::o200k_harmony_singleton(),
+        Some(Tokenizer::Cl100kBase) => tiktoken_rs::cl100k_base_singleton(),
+        Some(Tokenizer::P50kBase) => tiktoken_rs::p50k_base_singleton(),
+        Some(Tokenizer::P50kEdit) => tiktoken_rs::p50k_edit_singleton(),
+        Some(Tokenizer::R50kBase | Tokenizer::Gpt2) => tiktoken_rs::r50k_base_singleton(),
+        _ => tiktoken_rs::o200k_base_singleton(),
+    };
+    bpe.encode_ordinary(text).len()
+}
+
+/// Estimate the number of tokens of a text.
+///
+/// This is a heuristic: about 4 ASCII characters per token, and one token per other character
+/// (e.g. Japanese), which tends to overestimate rather than underestimate.
+/// Enable the `tokens` feature to count with a BPE tokenizer instead.
+#[cfg(not(feature = "tokens"))]
+pub fn estimate_text_tokens(text: &str) -> usize {
+    let ascii = text.chars().filter(char::is_ascii).count();
+    let other = text.chars().count() - ascii;
+    ascii.div_ceil(4) + other
+}
+
+/// Count the tokens of a text for the given model.
+///
+/// Without the `tokens` feature this is the same heuristic as `estimate_text_tokens`.
+#[cfg(not(feature = "tokens"))]
+pub fn count_text_tokens(text: &str, _model: &str) -> usize {
+    estimate_text_tokens(text)
+}
+
+/// Estimate the number of tokens a message uses in the prompt, including the message overhead.
+pub fn estimate_message_tokens(message: &Message) -> usize {
+    message_tokens(message, &estimate_text_tokens)
+}
+
+/// Estimate the number of tokens of a whole prompt, including the reply priming.
+pub fn estimate_prompt_tokens<'a>(messages: impl IntoIterator<Item = &'a Message>) -> usize {
+    TOKENS_PER_REPLY + messages.into_iter().map(estimate_message_tokens).sum::<usize>()
+}
+
+/// Count the tokens of a whole prompt for the given model, including the message overhead
+/// and the reply priming.
+pub fn count_prompt_tokens<'a>(messages: impl IntoIterator<Item = &'a Message>, model: &str) -> usize {
+    let text_tokens = |text: &str| count_text_tokens(text, model);
+    TOKENS_PER_REPLY + messages.into_iter().map(|message| message_tokens(message, &text_tokens)).sum::<usize>()
+}
+
+fn message_tokens(message: &Message, text_tokens: &dyn Fn(&str) -> usize) -> usize {
+    let (name, content_tokens) = match message {
+        Message::User { name, content, .. } => (name.as_deref(), contexts_tokens(content, text_tokens)),
+        Message::Tool { content, .. } => (None, contexts_tokens(content, text_tokens)),
+        Message::Assistant { name, content, tool_calls, .. } => {
+            let calls = tool_calls
+                .as_ref()
+                .map(|calls| text_tokens(&serde_json::to_string(calls).unwrap_or_default()))
+                .unwrap_or(0);
+            (name.as_deref(), contexts_tokens(content, text_tokens) + calls)
+        }
+        Message::System { name, content, .. } | Message::Developer { name, content, .. } => {
+            (name.as_deref(), text_tokens(content))
+        }
+    };
+    let name_tokens = name.map(|name| TOKENS_PER_NAME + text_tokens(name)).unwrap_or(0);
+    TOKENS_PER_MESSAGE + name_tokens + content_tokens
+}
+
+fn contexts_tokens(content: &[MessageContext], text_tokens: &dyn Fn(&str) -> usize) -> usize {
+    content
+        .iter()
+        .map(|context| match context {
+            MessageContext::Text(text) => text_tokens(text),
+            MessageContext::Image(image) => match image.detail.as_deref() {
+                Some("low") => LOW_DETAIL_IMAGE_TOKENS,
+                _ => HIGH_DETAIL_IMAGE_TOKENS,
+            },
+            // Decoded size divided by the bytes of compressed audio per token.
+            MessageContext::Audio(audio) => (audio.data.len() * 3 / 4).div_ceil(AUDIO_BYTES_PER_TOKEN),
+        })
+        .sum()
+}
@@ -1 +1,13 @@
-pub mod chat;
\ No newline at end of file
+pub mod chat;
+
+/// Re-export of `schemars`, so the argument types of `#[derive(Tool)]` can derive `JsonSchema`
+/// with the same version as this crate.
+#[cfg(feature = "derive")]
+pub use schemars;
+
+/// Re-exports used by the code generated by `#[derive(Tool)]`.
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod __private {
+    pub use serde_json;
+}
@@ -1,11 +1,12 @@
 use std::sync::Arc;
 
-use call_agent::chat::{client::{ModelConfig, OpenAIClient}, function::Tool, prompt::{Message, MessageContext}};
+use call_agent::chat::{client::{ModelConfig, OpenAIClient}, function::Tool, prompt::Message};
 use serde_json::Value;
 
 
 
 // Define a custom tool
+#[derive(Default)]
 pub struct TextLengthTool;
 
 impl TextLengthTool {
@@ -37,7 +38,7 @@ impl Tool for TextLengthTool {
     }
 
     fn run(&self, args: Value) -> Result<String, String> {
-        println!("{:?}", args);
+        log::debug!("text_length_tool args: {:?}", args);
         let text = args["text"].as_str().ok_or_else(|| "Missing 'text' parameter".to_string())?;
         let length = text.len();
         Ok(serde_json::json!({ "length": length }).to_string())
@@ -59,15 +60,11 @@ async fn main() {
     // create a model configuration
     let config = ModelConfig {
         model: "gpt-4o-mini".to_string(),
-        strict: None,
         max_completion_tokens: Some(1000),
         temperature: Some(0.8),
         top_p: Some(1.0),
-        parallel_tool_calls: None,
         presence_penalty: Some(0.0),
-        model_name: None,
-        reasoning_effort: None,
-        web_search_options: None, // Set to None if not using web search
+        ..Default::default()
     };
 
     // set the model configuration
@@ -83,15 +80,7 @@ async fn main() {
         std::io::stdin().read_line(&mut input).expect("Failed to read line");
 
         // create a prompt
-        let prompt = vec![Message::User 
-        {
-            name:Some("user".to_string()),
-            content:vec!
-            [
-                MessageContext::Text(input.trim().to_string()),
-            ], 
-        }
-        ];
+        let prompt = vec![Message::user_named("user", input.trim())];
 
         // add the prompt to the stream
         prompt_stream.add(prompt).await;
@@ -0,0 +1,79 @@
+//! Fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use std::{io::{Read, Write}, net::TcpListener, thread::{self, JoinHandle}};
+
+use call_agent::chat::function::Tool;
+use serde_json::Value;
+
+/// Start a server that answers one request with `body`.
+///
+/// With `keep_open`, the response is sent without a length and the connection is kept
+/// open until the client disconnects.
+///
+/// # Returns
+///
+/// The endpoint of the server and a handle to the head of the received request, in lowercase.
+pub fn serve(content_type: &'static str, body: &'static str, keep_open: bool) -> (String, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let end_point = format!("http://{}/v1", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+        if keep_open {
+            write!(stream, "HTTP/1.1 200 OK\r\ncontent-type: {}\r\nconnection: close\r\n\r\n{}", content_type, body).unwrap();
+        } else {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-type: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                content_type,
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+        stream.flush().unwrap();
+        if keep_open {
+            // Wait until the client drops the connection.
+            while matches!(stream.read(&mut buf), Ok(n) if n > 0) {}
+        }
+        String::from_utf8_lossy(&request).to_lowercase()
+    });
+    (end_point, server)
+}
+
+/// Start a server that answers one request with the server-sent events in `body`.
+pub fn serve_stream(body: &'static str, keep_open: bool) -> String {
+    serve("text/event-stream", body, keep_open).0
+}
+
+/// A synchronous tool that returns the length of its `text` argument.
+pub struct TextLengthTool;
+
+impl Tool for TextLengthTool {
+    fn def_name(&self) -> &str {
+        "text_length_tool"
+    }
+
+    fn def_description(&self) -> &str {
+        "Returns the length of the input text."
+    }
+
+    fn def_parameters(&self) -> Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": { "text": { "type": "string" } },
+            "required": ["text"]
+        })
+    }
+
+    fn run(&self, args: Value) -> Result<String, String> {
+        let text = args["text"].as_str().ok_or_else(|| "Missing 'text' parameter".to_string())?;
+        Ok(text.len().to_string())
+    }
+}
@@ -0,0 +1,40 @@
+#![cfg(feature = "derive")]
+
+use call_agent::{chat::function::Tool, schemars::JsonSchema};
+use serde::Deserialize;
+
+#[derive(Deserialize, JsonSchema)]
+#[schemars(crate = "call_agent::schemars")]
+struct UrlArgs {
+    /// The URL to fetch
+    url: String,
+}
+
+/// Fetches a URL.
+#[derive(Tool)]
+#[tool(args = UrlArgs)]
+struct HTTPTool;
+
+impl HTTPTool {
+    fn call(&self, args: UrlArgs) -> Result<String, String> {
+        Ok(args.url)
+    }
+}
+
+/// Fetches a URL as JSON.
+#[derive(Tool)]
+#[tool(args = UrlArgs)]
+struct FetchJSONData;
+
+impl FetchJSONData {
+    fn call(&self, args: UrlArgs) -> Result<String, String> {
+        Ok(args.url)
+    }
+}
+
+#[test]
+fn default_name_keeps_acronyms_together() {
+    assert_eq!(Tool::def_name(&HTTPTool), "http_tool");
+    assert_eq!(Tool::def_name(&FetchJSONData), "fetch_json_data");
+    assert_eq!(Tool::def_parameters(&HTTPTool)["required"], serde_json::json!(["url"]));
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use std::{collections::VecDeque, sync::Arc};
+
+use call_agent::chat::{
+    api::ToolChoice,
+    client::{ModelConfig, OpenAIClient},
+    err::ClientError,
+    prompt::Message,
+};
+use common::TextLengthTool;
+
+#[test]
+fn build_request_json_matches_the_api_shape() {
+    let mut client = OpenAIClient::new("http://localhost/v1", None);
+    client.def_tool(Arc::new(TextLengthTool));
+    let config = ModelConfig::builder().model("gpt-4o-mini").temperature(0.5).build();
+    let prompt = VecDeque::from(vec![Message::system("Be brief."), Message::user("How long is hello?")]);
+
+    let json = client.build_request_json(&prompt, Some(&ToolChoice::Auto), Some(&config)).unwrap();
+    assert_eq!(json, serde_json::json!({
+        "model": "gpt-4o-mini",
+        "temperature": 0.5,
+        "messages": [
+            { "role": "system", "content": "Be brief." },
+            { "role": "user", "content": "How long is hello?" },
+        ],
+        "tools": [{
+            "type": "function",
+            "function": {
+                "name": "text_length_tool",
+                "description": "Returns the length of the input text.",
+                "parameters": {
+                    "type": "object",
+                    "properties": { "text": { "type": "string" } },
+                    "required": ["text"]
+                },
+                "strict": false
+            }
+        }],
+        "tool_choice": "auto",
+    }));
+}
+
+#[test]
+fn invalid_names_are_rejected_or_sanitized() {
+    let mut client = OpenAIClient::new("http://localhost/v1", None);
+    let config = ModelConfig::new("test");
+    for name in ["John Doe", "太郎"] {
+        let prompt = VecDeque::from(vec![Message::user_named(name, "Hi")]);
+        let result = client.build_request_json(&prompt, None, Some(&config));
+        assert!(matches!(result, Err(ClientError::InvalidInput(message)) if message.contains(name)), "{}", name);
+    }
+
+    client.set_sanitize_names(true);
+    let prompt = VecDeque::from(vec![Message::user_named("John Doe", "Hi"), Message::user_named("太郎", "Hi")]);
+    let json = client.build_request_json(&prompt, None, Some(&config)).unwrap();
+    assert_eq!(json["messages"][0]["name"], "John_Doe");
+    assert_eq!(json["messages"][1]["name"], "__");
+}
+
+#[test]
+fn message_names_must_match_the_pattern() {
+    let system = |name: &str| Message::System {
+        name: Some(name.to_string()),
+        content: "Be brief.".to_string(),
+        created_at: None,
+        metadata: None,
+    };
+    let assistant = |name: &str| Message::Assistant {
+        name: Some(name.to_string()),
+        content: vec![],
+        tool_calls: None,
+        created_at: None,
+        metadata: None,
+    };
+
+    for name in ["alice_01", "gpt-4o"] {
+        assert!(Message::user_named(name, "Hi").validate().is_ok());
+        assert!(system(name).validate().is_ok());
+        assert!(assistant(name).validate().is_ok());
+    }
+    for name in ["", "John Doe", "名前", "a.b"] {
+        for message in [Message::user_named(name, "Hi"), system(name), assistant(name)] {
+            assert!(
+                matches!(message.validate(), Err(ClientError::InvalidInput(error)) if error.contains(&format!("{:?}", name))),
+                "{:?}",
+                name
+            );
+        }
+    }
+    assert!(Message::user("Hi").validate().is_ok());
+}
+
+#[test]
+fn developer_messages_can_be_sent_as_system() {
+    let mut client = OpenAIClient::new("http://localhost/v1", None);
+    let config = ModelConfig::new("test");
+    let prompt = VecDeque::from(vec![Message::developer("Be brief."), Message::user("Hi")]);
+
+    let json = client.build_request_json(&prompt, None, Some(&config)).unwrap();
+    assert_eq!(json["messages"][0], serde_json::json!({ "role": "developer", "content": "Be brief." }));
+
+    client.set_downgrade_developer_to_system(true);
+    let json = client.build_request_json(&prompt, None, Some(&config)).unwrap();
+    assert_eq!(json["messages"][0], serde_json::json!({ "role": "system", "content": "Be brief." }));
+    assert_eq!(json["messages"][1]["role"], "user");
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use call_agent::chat::{client::{ModelConfig, OpenAIClient}, err::ClientError, prompt::{Message, MessageContext}};
+use common::serve;
+
+/// The text of a user or assistant message.
+fn text(message: &Message) -> Option<&str> {
+    match message {
+        Message::User { content, .. } | Message::Assistant { content, .. } => match content.first() {
+            Some(MessageContext::Text(text)) => Some(text),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[tokio::test]
+async fn last_returns_the_most_recent_message() {
+    let client = OpenAIClient::new("http://localhost/v1", None);
+    let mut state = client.create_prompt();
+    assert!(state.last().await.is_none());
+
+    state.add(vec![Message::user("first"), Message::user("second")]).await;
+    assert_eq!(state.last().await.and_then(text), Some("second"));
+
+    state.add(vec![Message::assistant("third")]).await;
+    assert_eq!(state.last().await.and_then(text), Some("third"));
+
+    state.pop_last();
+    assert_eq!(state.last().await.and_then(text), Some("second"));
+}
+
+#[tokio::test]
+async fn system_prompt_stays_first() {
+    let client = OpenAIClient::new("http://localhost/v1", None);
+    let mut state = client.create_prompt();
+    state.set_system_prompt("Be brief.");
+    state.add(vec![Message::user("Hello")]).await;
+    state.add_last(vec![Message::user("Earlier")]).await;
+    assert!(matches!(&state.prompt[0], Message::System { content, .. } if content == "Be brief."));
+    assert_eq!(text(&state.prompt[1]), Some("Earlier"));
+
+    state.set_system_prompt("Be verbose.");
+    let systems = state.prompt.iter().filter(|message| matches!(message, Message::System { .. })).count();
+    assert_eq!(systems, 1);
+    assert!(matches!(&state.prompt[0], Message::System { content, .. } if content == "Be verbose."));
+
+    // The entry limit drops the oldest messages after the system prompt.
+    state.set_entry_limit(2).await;
+    assert_eq!(state.prompt.len(), 2);
+    assert!(matches!(&state.prompt[0], Message::System { .. }));
+    assert_eq!(text(&state.prompt[1]), Some("Hello"));
+}
+
+#[tokio::test]
+async fn clear_keeps_a_stamped_system_prompt() {
+    let client = OpenAIClient::new("http://localhost/v1", None);
+    let mut state = client.create_prompt();
+    state.set_system_prompt("Be brief.");
+    state.add(vec![Message::user("Hello")]).await;
+
+    state.clear().await;
+    assert_eq!(state.prompt.len(), 1);
+    assert!(matches!(&state.prompt[0], Message::System { content, .. } if content == "Be brief."));
+    assert!(state.prompt[0].created_at().is_some());
+}
+
+#[tokio::test]
+async fn keyless_client_sends_no_authorization() {
+    let (end_point, request) = serve(
+        "application/json",
+        r#"{"id":"1","object":"chat.completion","model":"llama3","choices":[{"index":0,"message":{"role":"assistant","content":"hi"},"finish_reason":"stop"}]}"#,
+        false,
+    );
+    let client = OpenAIClient::new_ollama(&end_point);
+    let mut state = client.create_prompt();
+    state.add(vec![Message::user("Hello")]).await;
+    state.generate(Some(&ModelConfig::new("llama3"))).await.unwrap();
+
+    let request = request.join().unwrap();
+    assert!(request.starts_with("post /v1/chat/completions"));
+    assert!(!request.contains("authorization:"));
+}
+
+#[tokio::test]
+async fn empty_choices_are_reported() {
+    let (end_point, _request) = serve(
+        "application/json",
+        r#"{"id":"1","object":"chat.completion","model":"gpt-4o-mini","choices":[]}"#,
+        false,
+    );
+    let client = OpenAIClient::new(&end_point, None);
+    let mut state = client.create_prompt();
+    state.add(vec![Message::user("Hello")]).await;
+
+    let result = state.generate(Some(&ModelConfig::new("gpt-4o-mini"))).await;
+    assert!(matches!(
+        result,
+        Err(ClientError::NoChoices { object, model }) if object == "chat.completion" && model.as_deref() == Some("gpt-4o-mini")
+    ));
+    assert_eq!(state.prompt.len(), 1);
+}
+
+#[tokio::test]
+async fn branch_does_not_affect_the_parent() {
+    let client = OpenAIClient::new("http://localhost/v1", None);
+    let mut parent = client.create_prompt();
+    parent.add(vec![Message::user("Checkpoint")]).await;
+
+    let mut branch = parent.branch();
+    branch.add(vec![Message::user("Speculative")]).await;
+    assert_eq!(parent.prompt.len(), 1);
+    assert_eq!(branch.prompt.len(), 2);
+
+    parent.add(vec![Message::user("Main line")]).await;
+    assert_eq!(branch.last().await.and_then(text), Some("Speculative"));
+    assert_eq!(parent.last().await.and_then(text), Some("Main line"));
+
+    let mut fork = parent.fork();
+    fork.clear().await;
+    assert_eq!(parent.prompt.len(), 2);
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use std::{collections::{HashSet, VecDeque}, sync::Arc};
+
+use call_agent::chat::{
+    client::{ModelConfig, OpenAIClient},
+    err::ClientError,
+    prompt::Message,
+};
+use common::{serve_stream, TextLengthTool};
+use futures::StreamExt;
+use serde_json::Value;
+use tokio_util::sync::CancellationToken;
+
+/// Whether every tool call in the prompt is answered by a tool message.
+fn tool_calls_answered(prompt: &VecDeque<Message>) -> bool {
+    let answered: HashSet<&str> = prompt
+        .iter()
+        .filter_map(|message| match message {
+            Message::Tool { tool_call_id, .. } => Some(tool_call_id.as_str()),
+            _ => None,
+        })
+        .collect();
+    prompt.iter().all(|message| match message {
+        Message::Assistant { tool_calls: Some(calls), .. } => calls.iter().all(|call| answered.contains(call.id.as_str())),
+        _ => true,
+    })
+}
+
+#[tokio::test]
+async fn cancel_during_tool_call_keeps_the_prompt_valid() {
+    let end_point = serve_stream(concat!(
+        r#"data: {"choices":[{"index":0,"delta":{"role":"assistant","content":"Let me count.","tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"text_length_tool","arguments":"{\"te"}}]}}]}"#,
+        "\n\n",
+    ), true);
+    let client = OpenAIClient::new(&end_point, None);
+    let mut state = client.create_prompt();
+    state.add(vec![Message::user("How long is hello?")]).await;
+
+    let cancel = CancellationToken::new();
+    let mut stream = state
+        .generate_stream(Some(&ModelConfig::new("test")), Some(&cancel))
+        .await
+        .unwrap();
+    let delta = stream.next().await.unwrap().unwrap();
+    assert!(delta.tool_calls.is_some());
+
+    cancel.cancel();
+    assert!(matches!(stream.next().await, Some(Err(ClientError::Cancelled))));
+    assert!(stream.next().await.is_none());
+    assert!(stream.finished_tool_calls().is_none());
+    drop(stream);
+
+    assert_eq!(state.prompt.len(), 2);
+    assert!(matches!(&state.prompt[1], Message::Assistant { tool_calls: None, .. }));
+    assert_eq!(state.last_assistant_text().as_deref(), Some("Let me count."));
+    assert!(tool_calls_answered(&state.prompt));
+}
+
+#[tokio::test]
+async fn finished_tool_calls_are_added_once_run() {
+    let end_point = serve_stream(concat!(
+        r#"data: {"choices":[{"index":0,"delta":{"role":"assistant","tool_calls":[{"index":0,"id":"call_1","type":"function","function":{"name":"text_length_tool","arguments":"{\"te"}}]}}]}"#,
+        "\n\n",
+        r#"data: {"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"xt\":\"hello\"}"}}]},"finish_reason":"tool_calls"}]}"#,
+        "\n\n",
+        "data: [DONE]\n\n",
+    ), false);
+    let mut client = OpenAIClient::new(&end_point, None);
+    client.def_tool(Arc::new(TextLengthTool));
+    let mut state = client.create_prompt();
+    state.add(vec![Message::user("How long is hello?")]).await;
+
+    let mut stream = state.generate_stream(Some(&ModelConfig::new("test")), None).await.unwrap();
+    assert!(stream.finished_tool_calls().is_none());
+    while let Some(delta) = stream.next().await {
+        delta.unwrap();
+    }
+    let calls = stream.finished_tool_calls().unwrap();
+    assert_eq!(calls[0].function.arguments, serde_json::json!({ "text": "hello" }));
+
+    let results = stream.run_tool_calls::<fn(&str, &Value)>(None).await.unwrap();
+    assert_eq!(results.len(), 1);
+    drop(stream);
+
+    assert_eq!(state.prompt.len(), 3);
+    assert!(matches!(&state.prompt[2], Message::Tool { tool_call_id, .. } if tool_call_id == "call_1"));
+    assert!(tool_calls_answered(&state.prompt));
+}
+
+#[tokio::test]
+async fn stream_closed_early_leaves_the_prompt_unchanged() {
+    let end_point = serve_stream(concat!(
+        r#"data: {"choices":[{"index":0,"delta":{"role":"assistant","content":"Hello"}}]}"#,
+        "\n\n",
+    ), false);
+    let client = OpenAIClient::new(&end_point, None);
+    let mut state = client.create_prompt();
+    state.add(vec![Message::user("Hi")]).await;
+
+    let mut stream = state.generate_stream(Some(&ModelConfig::new("test")), None).await.unwrap();
+    assert_eq!(stream.next().await.unwrap().unwrap().content.as_deref(), Some("Hello"));
+    assert!(matches!(stream.next().await, Some(Err(ClientError::NetworkError))));
+    assert!(stream.next().await.is_none());
+    drop(stream);
+
+    assert_eq!(state.prompt.len(), 1);
+}
+
+#[tokio::test]
+async fn stream_rejects_several_choices() {
+    let client = OpenAIClient::new("http://localhost/v1", None);
+    let mut state = client.create_prompt();
+    state.add(vec![Message::user("Hi")]).await;
+
+    let config = ModelConfig::builder().model("test").n(2).build();
+    assert!(matches!(state.generate_stream(Some(&config), None).await, Err(ClientError::InvalidInput(_))));
+}
+
+#[tokio::test]
+async fn cancel_keeps_only_the_yielded_text() {
+    // Both chunks arrive together, but only the first one is read before cancelling.
+    let end_point = serve_stream(concat!(
+        r#"data: {"choices":[{"index":0,"delta":{"role":"assistant","content":"Hello"}}]}"#,
+        "\n\n",
+        r#"data: {"choices":[{"index":0,"delta":{"content":", world"}}]}"#,
+        "\n\n",
+    ), true);
+    let client = OpenAIClient::new(&end_point, None);
+    let mut state = client.create_prompt();
+    state.add(vec![Message::user("Hi")]).await;
+
+    let cancel = CancellationToken::new();
+    let mut stream = state
+        .generate_stream(Some(&ModelConfig::new("test")), Some(&cancel))
+        .await
+        .unwrap();
+    assert_eq!(stream.next().await.unwrap().unwrap().content.as_deref(), Some("Hello"));
+    cancel.cancel();
+    assert!(matches!(stream.next().await, Some(Err(ClientError::Cancelled))));
+    drop(stream);
+
+    assert_eq!(state.last_assistant_text().as_deref(), Some("Hello"));
+}
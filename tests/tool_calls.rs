@@ -0,0 +1,210 @@
+mod common;
+
+use std::{collections::VecDeque, sync::{Arc, Condvar, Mutex}, time::Duration};
+
+use async_trait::async_trait;
+use call_agent::chat::{
+    api::{APIResponseHeaders, ToolChoice},
+    backend::ChatBackend,
+    client::{APIResult, ModelConfig, OpenAIClient},
+    err::ClientError,
+    function::{FunctionCall, FunctionCallInner, Tool, ToolDef},
+    prompt::{Message, MessageContext},
+};
+use common::TextLengthTool;
+use reqwest::header::HeaderMap;
+use serde_json::Value;
+
+/// Tools waiting for each other: each call waits until `parties` calls are running.
+struct Rendezvous {
+    arrived: Mutex<usize>,
+    all_arrived: Condvar,
+    parties: usize,
+}
+
+/// A synchronous tool that blocks its thread until every other party has started.
+struct RendezvousTool {
+    name: &'static str,
+    rendezvous: Arc<Rendezvous>,
+}
+
+impl Tool for RendezvousTool {
+    fn def_name(&self) -> &str {
+        self.name
+    }
+
+    fn def_description(&self) -> &str {
+        "Waits for the other tools."
+    }
+
+    fn def_parameters(&self) -> Value {
+        serde_json::json!({ "type": "object", "properties": {} })
+    }
+
+    fn run(&self, _args: Value) -> Result<String, String> {
+        let rendezvous = &self.rendezvous;
+        let mut arrived = rendezvous.arrived.lock().unwrap();
+        *arrived += 1;
+        rendezvous.all_arrived.notify_all();
+        // Run one after another, the first call would never see the second one start.
+        let (_arrived, wait) = rendezvous
+            .all_arrived
+            .wait_timeout_while(arrived, Duration::from_secs(10), |arrived| *arrived < rendezvous.parties)
+            .unwrap();
+        if wait.timed_out() {
+            Err("the other tools did not start".to_string())
+        } else {
+            Ok(self.name.to_string())
+        }
+    }
+}
+
+fn call(id: &str, name: &str) -> FunctionCall {
+    FunctionCall {
+        id: id.to_string(),
+        tool_type: "function".to_string(),
+        function: FunctionCallInner {
+            name: name.to_string(),
+            arguments: serde_json::json!({}),
+        },
+    }
+}
+
+#[tokio::test]
+async fn sync_tools_run_in_parallel() {
+    let rendezvous = Arc::new(Rendezvous { arrived: Mutex::new(0), all_arrived: Condvar::new(), parties: 2 });
+    let mut client = OpenAIClient::new("http://localhost/v1", None);
+    client.def_tool(Arc::new(RendezvousTool { name: "first", rendezvous: Arc::clone(&rendezvous) }));
+    client.def_tool(Arc::new(RendezvousTool { name: "second", rendezvous }));
+
+    let results = client
+        .run_tool_calls::<fn(&str, &Value)>(&[call("call_1", "first"), call("call_2", "second")], None)
+        .await
+        .unwrap();
+
+    let texts: Vec<_> = results
+        .iter()
+        .map(|message| match message {
+            Message::Tool { content, .. } => match content.first() {
+                Some(MessageContext::Text(text)) => text.as_str(),
+                _ => "",
+            },
+            _ => "",
+        })
+        .collect();
+    assert_eq!(texts, ["first", "second"]);
+}
+
+/// A backend that always replies with a call of `text_length_tool`.
+struct ToolCallBackend;
+
+#[async_trait]
+impl ChatBackend for ToolCallBackend {
+    async fn complete(
+        &self,
+        _prompt: &VecDeque<Message>,
+        _tools: &[ToolDef],
+        _config: &ModelConfig,
+        _tool_choice: Option<&ToolChoice>,
+    ) -> Result<APIResult, ClientError> {
+        let response = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "model": "test",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "Let me count.",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "text_length_tool", "arguments": "{\"text\":\"hello\"}" }
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }]
+        }))
+        .unwrap();
+        Ok(APIResult {
+            response,
+            headers: APIResponseHeaders::from_headers(&HeaderMap::new()),
+        })
+    }
+}
+
+#[tokio::test]
+async fn tool_entry_points_leave_the_same_prompt() {
+    let mut client = OpenAIClient::new("http://localhost/v1", None);
+    client.def_tool(Arc::new(TextLengthTool));
+    client.set_model_config(&ModelConfig::builder().model("test").model_name("assistant").build());
+
+    let mut can_use = client.create_prompt_with_backend(ToolCallBackend);
+    let mut use_tool = client.create_prompt_with_backend(ToolCallBackend);
+    let mut with_tool = client.create_prompt_with_backend(ToolCallBackend);
+    for state in [&mut can_use, &mut use_tool, &mut with_tool] {
+        state.add(vec![Message::user("How long is hello?")]).await;
+    }
+
+    can_use.generate_can_use_tool::<fn(&str, &Value)>(None, None).await.unwrap();
+    use_tool.generate_use_tool::<fn(&str, &Value)>(None, None).await.unwrap();
+    with_tool
+        .generate_with_tool::<fn(&str, &Value)>(None, "text_length_tool", None)
+        .await
+        .unwrap();
+
+    // The request body leaves out the timestamps, which may differ between the states.
+    let messages = |prompt: &VecDeque<Message>| client.build_request_json(prompt, None, None).unwrap()["messages"].clone();
+    let expected = messages(&can_use.prompt);
+    assert_eq!(expected, serde_json::json!([
+        { "role": "user", "content": "How long is hello?" },
+        {
+            "role": "assistant",
+            "name": "assistant",
+            "content": "Let me count.",
+            "tool_calls": [{
+                "id": "call_1",
+                "type": "function",
+                "function": { "name": "text_length_tool", "arguments": "{\"text\":\"hello\"}" }
+            }]
+        },
+        { "role": "tool", "tool_call_id": "call_1", "content": "5" },
+    ]));
+    assert_eq!(messages(&use_tool.prompt), expected);
+    assert_eq!(messages(&with_tool.prompt), expected);
+}
+
+#[tokio::test]
+async fn unknown_tool_leaves_the_prompt_unchanged() {
+    // `text_length_tool` is not registered.
+    let mut client = OpenAIClient::new("http://localhost/v1", None);
+    client.set_model_config(&ModelConfig::new("test"));
+
+    let mut state = client.create_prompt_with_backend(ToolCallBackend);
+    state.add(vec![Message::user("How long is hello?")]).await;
+
+    let result = state.generate_can_use_tool::<fn(&str, &Value)>(None, None).await;
+    assert!(matches!(result, Err(ClientError::ToolNotFound(name)) if name == "text_length_tool"));
+    assert_eq!(state.prompt.len(), 1);
+
+    // Once the tool is registered, the prompt is valid for the next request.
+    state.client.def_tool(Arc::new(TextLengthTool));
+    state.generate_can_use_tool::<fn(&str, &Value)>(None, None).await.unwrap();
+    assert_eq!(state.prompt.len(), 3);
+}
+
+#[test]
+fn strict_follows_the_request_config() {
+    let mut client = OpenAIClient::new("http://localhost/v1", None);
+    client.def_tool(Arc::new(TextLengthTool));
+    client.set_model_config(&ModelConfig::builder().model("test").strict(true).parallel_tool_calls(false).build());
+
+    let strict = |config: Option<&ModelConfig>| {
+        client.build_request_json(&VecDeque::new(), None, config).unwrap()["tools"][0]["function"]["strict"].clone()
+    };
+    assert_eq!(strict(None), serde_json::json!(true));
+
+    // Parallel tool calls enabled for one request turn `strict` off for that request.
+    let parallel = ModelConfig::builder().model("test").strict(true).parallel_tool_calls(true).build();
+    assert_eq!(strict(Some(&parallel)), serde_json::json!(false));
+}